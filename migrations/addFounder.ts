@@ -5,6 +5,10 @@ const FOUNDER_ADDRESSES = [
   'GNYVxhkUqFWKN52sHwChi3yX5ouRCnJ7US1La85fXcbt'
 ];
 
+// Vesting schedule applied to every founder added by this script.
+const CLIFF_SECONDS = 0;
+const VESTING_SECONDS = 0;
+
 const main = async () => {
   const provider = anchor.AnchorProvider.env();
   anchor.setProvider(provider);
@@ -27,7 +31,9 @@ const main = async () => {
   console.log("Expected deployer from metadata:", metadataAccount.deployer.toBase58());
   console.log("Your wallet:", wallet.publicKey.toBase58());
 
-  const pool = await program.account.foundersPool.fetch(foundersPool) as any;
+  const specs = [];
+  const founderStates = [];
+  const seen = new Set<string>();
 
   for (const address of FOUNDER_ADDRESSES) {
     let pubkey: PublicKey;
@@ -38,27 +44,51 @@ const main = async () => {
       continue;
     }
 
-    const alreadyExists = pool.founders
-      .slice(0, pool.founderCount)
-      .some((f: PublicKey) => f.toBase58() === pubkey.toBase58());
+    if (seen.has(pubkey.toBase58())) {
+      console.log(`⚠️ Listed twice: ${address}`);
+      continue;
+    }
+    seen.add(pubkey.toBase58());
+
+    const [founderState] = PublicKey.findProgramAddressSync(
+      [Buffer.from('founder'), pubkey.toBuffer()],
+      program.programId
+    );
+
+    const alreadyExists = await program.account.founder.fetchNullable(founderState);
 
     if (alreadyExists) {
       console.log(`⚠️ Already in pool: ${address}`);
       continue;
     }
 
-    const tx = await program.methods
-      .addFounder(pubkey)
-      .accounts({
-        metadata,
-        foundersPool,
-        deployer: wallet.publicKey,
-      })
-      .rpc();
-
-    console.log(`✅ Added founder: ${address}`);
-    console.log(`Tx Signature: ${tx}`);
+    specs.push({
+      founder: pubkey,
+      shares: null,
+      cliffSeconds: new anchor.BN(CLIFF_SECONDS),
+      vestingSeconds: new anchor.BN(VESTING_SECONDS),
+    });
+    founderStates.push({ pubkey: founderState, isSigner: false, isWritable: true });
   }
+
+  if (specs.length === 0) {
+    console.log('Nothing to add');
+    return;
+  }
+
+  const tx = await program.methods
+    .addFounders(specs)
+    .accounts({
+      metadata,
+      foundersPool,
+      deployer: wallet.publicKey,
+      systemProgram: anchor.web3.SystemProgram.programId,
+    })
+    .remainingAccounts(founderStates)
+    .rpc();
+
+  console.log(`✅ Added ${specs.length} founder(s)`);
+  console.log(`Tx Signature: ${tx}`);
 };
 
 main().catch((err) => {
@@ -1,10 +1,57 @@
 use anchor_lang::prelude::*;
+use anchor_lang::Discriminator;
+use anchor_lang::solana_program::program_pack::Pack;
 use anchor_spl::associated_token::AssociatedToken;
 use anchor_spl::token::spl_token::instruction::AuthorityType;
-use anchor_spl::token::{self, Burn, Mint, MintTo, Token, TokenAccount, Transfer};
+use anchor_spl::token::{self, Burn, CloseAccount, Mint, MintTo, Token, TokenAccount, Transfer};
 
 declare_id!("2XRYELdk3k9XFs7JkSw55sz5aZWqNeZhY71rZr2pYETu");
 
+/// Share of the team fee paid to whoever cranks a matured position, in bps.
+pub const KEEPER_TIP_BPS: u64 = 1_000;
+
+/// Rollover fees are the entry fees of the new tier divided by this factor.
+pub const ROLLOVER_FEE_DIVISOR: u64 = 2;
+
+/// Ceiling on the combined exit fee, in bps. Early penalties are clamped so
+/// an exit always pays out something.
+pub const MAX_EXIT_FEE_BPS: u64 = 9_999;
+
+/// Ceiling on the combined bps of a single lock fee tier.
+pub const MAX_LOCK_FEE_BPS: u64 = 2_500;
+
+/// Oldest price observation, in slots, that can trigger a leverage order.
+pub const MAX_PRICE_AGE_SLOTS: u64 = 150;
+
+/// Maximum number of anchor tiers in the lock fee curve.
+pub const MAX_FEE_TIERS: usize = 16;
+
+/// Hard ceiling for `ProtocolState::max_leverage_bps` (10x).
+pub const MAX_LEVERAGE_BPS_CAP: u64 = 100_000;
+
+/// Maximum number of open leverage positions tracked per user.
+pub const MAX_LEVERAGE_POSITIONS: usize = 16;
+
+/// Length bounds of a referral code, in bytes.
+pub const MIN_REFERRAL_CODE_LEN: usize = 3;
+pub const MAX_REFERRAL_CODE_LEN: usize = 32;
+
+/// Maximum number of referral rate tiers.
+pub const MAX_REFERRAL_TIERS: usize = 8;
+
+/// Maximum number of recipients in the team fee split.
+pub const MAX_FEE_RECIPIENTS: usize = 8;
+
+/// Layout version written to every `LockedTokenState`. Locks created before
+/// versioning use the legacy layout and must go through `migrate_lock_state`.
+pub const LOCK_STATE_VERSION: u8 = 1;
+
+/// Fixed-point scale of `FoundersPool::acc_per_share`.
+pub const ACC_PRECISION: u128 = 1_000_000_000_000;
+
+/// Slot count of the legacy `FoundersPool`, where each founder was owed one
+/// sixtieth of everything collected.
+pub const LEGACY_FOUNDER_SLOTS: u64 = 60;
 
 #[program]
 pub mod up_only {
@@ -168,8 +215,9 @@ pub mod up_only {
         let pool = &mut ctx.accounts.founders_pool;
         pool.total_collected = 0;
         pool.founder_count = 0;
-        pool.founders = vec![Pubkey::default(); 60];
-        pool.claim_status = vec![0u64; 60];
+        pool.total_shares = 0;
+        pool.acc_per_share = 0;
+        pool.undistributed = 0;
 
         if ctx.accounts.founder_pool_token_account.lamports() == 0 {
             let cpi_ctx = CpiContext::new(
@@ -189,820 +237,865 @@ pub mod up_only {
         Ok(())
     }
 
-    pub fn buy_and_lock_token(
-        ctx: Context<BuyAndLockToken>,
-        amount: u64,
-        lock_hour: u64,
-        referral: Option<Pubkey>,
-    ) -> Result<()> {
-        let clock = Clock::get()?;
-        let lock_state = &mut ctx.accounts.lock_state;
-        require!(!lock_state.initialized, CustomError::AlreadyInitialized);
+    pub fn initialize_protocol_state(ctx: Context<InitializeProtocolState>) -> Result<()> {
         require!(
-            matches!(lock_hour, 1 | 2 | 3 | 4 | 6 | 8 | 12),
-            CustomError::InvalidLockPeriod
+            ctx.accounts.authority.key() == ctx.accounts.metadata.deployer,
+            CustomError::Unauthorized
         );
 
-        require!(ctx.accounts.metadata.initialized, CustomError::AlreadyInitialized);
-        
-        let payment_token_mint = ctx.accounts.metadata.payment_token;
+        let state = &mut ctx.accounts.protocol_state;
+        state.early_penalty_min_bps = 50;
+        state.early_penalty_max_bps = 50;
+        state.early_penalty_to_liquidity = false;
+        state.min_lock_seconds = 3600;
+        state.max_lock_seconds = 30 * 24 * 3600;
+        state.fee_tiers = default_fee_tiers();
+        state.max_leverage_bps = 50_000;
+        state.total_borrowed = 0;
+        state.max_total_borrowed = u64::MAX;
+        state.max_borrow_utilization_bps = 10_000;
+        state.bad_debt = 0;
+        state.referral_tiers = vec![ReferralTier {
+            min_volume: 0,
+            share_bps: 5_000,
+        }];
+
+        Ok(())
+    }
+
+    pub fn set_borrow_caps(
+        ctx: Context<UpdateProtocolState>,
+        max_total_borrowed: u64,
+        max_borrow_utilization_bps: u64,
+    ) -> Result<()> {
         require!(
-            ctx.accounts.vault_token_account.mint == ctx.accounts.metadata.mint,
-            CustomError::InvalidTokenMint
+            ctx.accounts.authority.key() == ctx.accounts.metadata.deployer,
+            CustomError::Unauthorized
         );
         require!(
-            ctx.accounts.user_usdc_account.mint == payment_token_mint,
-            CustomError::InvalidTokenMint
+            max_borrow_utilization_bps > 0,
+            CustomError::InvalidBorrowCaps
         );
+
+        let state = &mut ctx.accounts.protocol_state;
+        state.max_total_borrowed = max_total_borrowed;
+        state.max_borrow_utilization_bps = max_borrow_utilization_bps;
+
+        Ok(())
+    }
+
+    pub fn set_max_leverage(ctx: Context<UpdateProtocolState>, max_leverage_bps: u64) -> Result<()> {
         require!(
-            ctx.accounts.deployer_usdc_account.mint == payment_token_mint,
-            CustomError::InvalidTokenMint
+            ctx.accounts.authority.key() == ctx.accounts.metadata.deployer,
+            CustomError::Unauthorized
         );
         require!(
-            ctx.accounts.program_payment_token_account.mint == payment_token_mint,
-            CustomError::InvalidTokenMint
+            max_leverage_bps > 10_000 && max_leverage_bps <= MAX_LEVERAGE_BPS_CAP,
+            CustomError::InvalidLeverageMultiplier
         );
 
-        let expected_deployer_usdc_account = anchor_spl::associated_token::get_associated_token_address(
-            &ctx.accounts.metadata.team,
-            &ctx.accounts.metadata.payment_token,
+        ctx.accounts.protocol_state.max_leverage_bps = max_leverage_bps;
+
+        Ok(())
+    }
+
+    pub fn set_lock_fee_tiers(
+        ctx: Context<UpdateProtocolState>,
+        min_lock_seconds: u64,
+        max_lock_seconds: u64,
+        fee_tiers: Vec<FeeTier>,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.metadata.deployer,
+            CustomError::Unauthorized
         );
         require!(
-            ctx.accounts.deployer_usdc_account.key() == expected_deployer_usdc_account,
-            CustomError::InvalidDeployerUsdcAccount
+            min_lock_seconds > 0 && min_lock_seconds <= max_lock_seconds,
+            CustomError::InvalidLockPeriod
         );
-
-        let (expected_pool_authority, _) = Pubkey::find_program_address(
-            &[b"token_account", ctx.accounts.metadata.payment_token.as_ref()],
-            ctx.program_id,
+        require!(
+            !fee_tiers.is_empty() && fee_tiers.len() <= MAX_FEE_TIERS,
+            CustomError::InvalidFeeTiers
         );
         require!(
-            ctx.accounts.pool_authority.key() == expected_pool_authority,
-            CustomError::InvalidOwner
+            fee_tiers
+                .windows(2)
+                .all(|pair| pair[0].lock_seconds < pair[1].lock_seconds),
+            CustomError::InvalidFeeTiers
         );
+        for tier in fee_tiers.iter() {
+            let total_bps = tier
+                .liquidity_bps
+                .checked_add(tier.team_bps)
+                .and_then(|v| v.checked_add(tier.founder_bps))
+                .ok_or(CustomError::MathOverflow)?;
+            require!(total_bps <= MAX_LOCK_FEE_BPS, CustomError::InvalidFeeTiers);
+        }
+
+        let state = &mut ctx.accounts.protocol_state;
+        state.min_lock_seconds = min_lock_seconds;
+        state.max_lock_seconds = max_lock_seconds;
+        state.fee_tiers = fee_tiers;
+
+        Ok(())
+    }
 
+    /// Replaces the referral rate tiers. Tiers must be sorted by strictly
+    /// increasing `min_volume`, starting at zero.
+    pub fn set_referral_tiers(
+        ctx: Context<UpdateProtocolState>,
+        tiers: Vec<ReferralTier>,
+    ) -> Result<()> {
         require!(
-            ctx.accounts.user_up_usdc_account.mint == ctx.accounts.metadata.up_usdc_mint,
-            CustomError::InvalidTokenMint
+            ctx.accounts.authority.key() == ctx.accounts.metadata.deployer,
+            CustomError::Unauthorized
         );
         require!(
-            ctx.accounts.program_up_usdc_account.mint == ctx.accounts.metadata.up_usdc_mint,
-            CustomError::InvalidTokenMint
+            !tiers.is_empty() && tiers.len() <= MAX_REFERRAL_TIERS,
+            CustomError::InvalidReferralTiers
         );
+        require!(tiers[0].min_volume == 0, CustomError::InvalidReferralTiers);
         require!(
-            ctx.accounts.up_usdc_mint.key() == ctx.accounts.metadata.up_usdc_mint,
-            CustomError::InvalidTokenMint
-        );
-
-        let expected_founder_pool_token_account = anchor_spl::associated_token::get_associated_token_address(
-            &ctx.accounts.founder_authority.key(),
-            &ctx.accounts.metadata.payment_token,
+            tiers.windows(2).all(|w| w[0].min_volume < w[1].min_volume),
+            CustomError::InvalidReferralTiers
         );
         require!(
-            ctx.accounts.founder_pool_token_account.key() == expected_founder_pool_token_account,
-            CustomError::InvalidFounderPoolTokenAccount
+            tiers.iter().all(|tier| tier.share_bps <= 10_000),
+            CustomError::InvalidReferralTiers
         );
 
-        let expected_program_up_usdc_account = anchor_spl::associated_token::get_associated_token_address(
-            &ctx.accounts.up_pool_authority.key(),
-            &ctx.accounts.metadata.up_usdc_mint,
+        ctx.accounts.protocol_state.referral_tiers = tiers;
+
+        Ok(())
+    }
+
+    pub fn set_early_penalty(
+        ctx: Context<UpdateProtocolState>,
+        min_bps: u64,
+        max_bps: u64,
+        to_liquidity: bool,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.metadata.deployer,
+            CustomError::Unauthorized
         );
         require!(
-            ctx.accounts.program_up_usdc_account.key() == expected_program_up_usdc_account,
-            CustomError::InvalidProgramUpUsdcAccount
+            min_bps <= max_bps && max_bps <= 10_000,
+            CustomError::InvalidPenaltyConfig
         );
 
-        let config = get_lock_fee_config(lock_hour);
-        let total_usdc = amount;
-        let team_share = total_usdc * config.team_bps / 10_000;
-        let founder_fee = total_usdc * config.founder_bps / 10_000;
-        let locked_share = total_usdc * config.liquidity_bps / 10_000;
-        let usdc_for_tokens = total_usdc - team_share - founder_fee - locked_share;
+        let state = &mut ctx.accounts.protocol_state;
+        state.early_penalty_min_bps = min_bps;
+        state.early_penalty_max_bps = max_bps;
+        state.early_penalty_to_liquidity = to_liquidity;
 
-        let liquidity_balance =
-            token::accessor::amount(&ctx.accounts.program_up_usdc_account.to_account_info())?;
-        let token_supply = ctx.accounts.token_mint.supply;
+        Ok(())
+    }
 
-        let mut price_start =
-            (liquidity_balance as u128) * 1_000_000_000 / (token_supply.max(1) as u128);
-        if price_start == 0 {
-            price_start = 1;
-        }
-        let estimated_tokens = (usdc_for_tokens as u128) * 1_000_000_000 / price_start;
-        let liquidity_growth =
-            (liquidity_balance as u128) + (usdc_for_tokens as u128) + (locked_share as u128);
-        let price_end =
-            liquidity_growth * 1_000_000_000 / (((token_supply as u128) + estimated_tokens).max(1));
-        let avg_price = (price_start + price_end) / 2;
-        require!(avg_price > 0, CustomError::InsufficientAmount);
-        let usdc_for_tokens_u128 = usdc_for_tokens as u128;
-        let multiplier = 1_000_000_000u128;
-        let numerator = usdc_for_tokens_u128 * multiplier;
-        let mintable_tokens = (numerator / avg_price) as u64;
-        
-        require!(mintable_tokens > 0, CustomError::InsufficientAmount);
-        
-        if let Some(ref_pubkey) = referral {
-            let referral_token_account = ctx
-                .accounts
-                .referral_usdc_account
-                .as_ref()
-                .ok_or(CustomError::MissingReferralAccount)?;
-            require!(
-                referral_token_account.owner == ref_pubkey,
-                CustomError::InvalidReferral
-            );
+    pub fn initialize_team_treasury(ctx: Context<InitializeTeamTreasury>) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.metadata.deployer,
+            CustomError::Unauthorized
+        );
 
-            token::transfer(
-                CpiContext::new(
-                    ctx.accounts.token_program.to_account_info(),
-                    Transfer {
-                        from: ctx.accounts.user_usdc_account.to_account_info(),
-                        to: referral_token_account.to_account_info(),
-                        authority: ctx.accounts.user.to_account_info(),
-                    },
-                ),
-                team_share / 2,
-            )?;
+        Ok(())
+    }
 
-            token::transfer(
-                CpiContext::new(
-                    ctx.accounts.token_program.to_account_info(),
-                    Transfer {
-                        from: ctx.accounts.user_usdc_account.to_account_info(),
-                        to: ctx.accounts.deployer_usdc_account.to_account_info(),
-                        authority: ctx.accounts.user.to_account_info(),
-                    },
-                ),
-                team_share / 2,
-            )?;
-        } else {
-            token::transfer(
-                CpiContext::new(
-                    ctx.accounts.token_program.to_account_info(),
-                    Transfer {
-                        from: ctx.accounts.user_usdc_account.to_account_info(),
-                        to: ctx.accounts.deployer_usdc_account.to_account_info(),
-                        authority: ctx.accounts.user.to_account_info(),
-                    },
-                ),
-                team_share,
-            )?;
-        }
+    /// Moves accrued team fees out of the treasury. Only `metadata.team` may
+    /// withdraw, to any payment token account it chooses. Amounts owed to
+    /// fee split recipients stay in the treasury.
+    pub fn withdraw_team_fees(ctx: Context<WithdrawTeamFees>, amount: u64) -> Result<()> {
+        require!(
+            ctx.accounts.team.key() == ctx.accounts.metadata.team,
+            CustomError::Unauthorized
+        );
+        let available = ctx
+            .accounts
+            .team_treasury
+            .amount
+            .saturating_sub(ctx.accounts.fee_split.outstanding);
+        require!(
+            amount > 0 && amount <= available,
+            CustomError::InsufficientAmount
+        );
+
+        let treasury_bump = ctx.bumps.team_treasury;
+        let treasury_seeds: &[&[&[u8]]] = &[&[b"team_treasury", &[treasury_bump]]];
 
         token::transfer(
-            CpiContext::new(
+            CpiContext::new_with_signer(
                 ctx.accounts.token_program.to_account_info(),
-                token::Transfer {
-                    from: ctx.accounts.user_usdc_account.to_account_info(),
-                    to: ctx.accounts.founder_pool_token_account.to_account_info(),
-                    authority: ctx.accounts.user.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.team_treasury.to_account_info(),
+                    to: ctx.accounts.destination_token_account.to_account_info(),
+                    authority: ctx.accounts.team_treasury.to_account_info(),
                 },
+                treasury_seeds,
             ),
-            founder_fee,
+            amount,
         )?;
 
-        let pool = &mut ctx.accounts.founders_pool;
-        pool.total_collected += founder_fee;
+        Ok(())
+    }
+
+    pub fn initialize_fee_split(ctx: Context<InitializeFeeSplit>) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.metadata.deployer,
+            CustomError::Unauthorized
+        );
+
+        let fee_split = &mut ctx.accounts.fee_split;
+        fee_split.outstanding = 0;
+        fee_split.recipients = Vec::new();
+
+        Ok(())
+    }
+
+    /// Replaces the list of team fee recipients. Recipients that stay keep
+    /// their accrued balance; one with an unclaimed balance cannot be removed.
+    /// An empty list leaves the whole team fee with the team.
+    pub fn set_fee_split(
+        ctx: Context<UpdateFeeSplit>,
+        recipients: Vec<FeeRecipientSpec>,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.metadata.deployer,
+            CustomError::Unauthorized
+        );
+        validate_fee_split(&recipients)?;
+
+        let fee_split = &mut ctx.accounts.fee_split;
+        for existing in fee_split.recipients.iter() {
+            require!(
+                existing.accrued == 0
+                    || recipients.iter().any(|spec| spec.recipient == existing.recipient),
+                CustomError::FeeRecipientHasBalance
+            );
+        }
+
+        fee_split.recipients = recipients
+            .iter()
+            .map(|spec| FeeRecipient {
+                recipient: spec.recipient,
+                bps: spec.bps,
+                accrued: fee_split
+                    .recipients
+                    .iter()
+                    .find(|existing| existing.recipient == spec.recipient)
+                    .map_or(0, |existing| existing.accrued),
+            })
+            .collect();
+
+        Ok(())
+    }
+
+    /// Pays the caller's accrued fee split balance out of the team treasury.
+    pub fn claim_fee_split(ctx: Context<ClaimFeeSplit>) -> Result<()> {
+        let fee_split = &mut ctx.accounts.fee_split;
+        let recipient_key = ctx.accounts.recipient.key();
+        let entry = fee_split
+            .recipients
+            .iter_mut()
+            .find(|entry| entry.recipient == recipient_key)
+            .ok_or(CustomError::NotFeeRecipient)?;
+
+        let amount = entry.accrued;
+        require!(amount > 0, CustomError::NothingToClaim);
+        entry.accrued = 0;
+        fee_split.outstanding = fee_split
+            .outstanding
+            .checked_sub(amount)
+            .ok_or(CustomError::MathUnderflow)?;
+
+        let treasury_bump = ctx.bumps.team_treasury;
+        let treasury_seeds: &[&[&[u8]]] = &[&[b"team_treasury", &[treasury_bump]]];
 
         token::transfer(
-            CpiContext::new(
+            CpiContext::new_with_signer(
                 ctx.accounts.token_program.to_account_info(),
                 Transfer {
-                    from: ctx.accounts.user_usdc_account.to_account_info(),
-                    to: ctx.accounts.program_payment_token_account.to_account_info(),
-                    authority: ctx.accounts.user.to_account_info(),
+                    from: ctx.accounts.team_treasury.to_account_info(),
+                    to: ctx.accounts.recipient_token_account.to_account_info(),
+                    authority: ctx.accounts.team_treasury.to_account_info(),
                 },
+                treasury_seeds,
             ),
-            usdc_for_tokens + locked_share,
+            amount,
         )?;
 
-        let up_usdc_mint_bump = ctx.bumps.up_usdc_mint_authority;
-        let up_usdc_signer_seeds: &[&[&[u8]]] =
-            &[&[b"up_usdc_mint_authority", &[up_usdc_mint_bump]]];
+        Ok(())
+    }
 
-        token::mint_to(
-            CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                token::MintTo {
-                    mint: ctx.accounts.up_usdc_mint.to_account_info(),
-                    to: ctx.accounts.program_up_usdc_account.to_account_info(),
-                    authority: ctx.accounts.up_usdc_mint_authority.to_account_info(),
-                },
-                up_usdc_signer_seeds,
-            ),
-            usdc_for_tokens + locked_share,
-        )?;
+    pub fn initialize_referral_pool(ctx: Context<InitializeReferralPool>) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.metadata.deployer,
+            CustomError::Unauthorized
+        );
 
-        let mint_bump = ctx.bumps.mint_authority;
-        let signer_seeds: &[&[&[u8]]] = &[&[b"mint_authority", &[mint_bump]]];
+        Ok(())
+    }
 
-        token::mint_to(
+    /// Creates the caller's `ReferralAccount`. Positions can only name a
+    /// referrer that has registered.
+    pub fn register_referrer(ctx: Context<RegisterReferrer>) -> Result<()> {
+        let referral_account = &mut ctx.accounts.referral_account;
+        referral_account.referrer = ctx.accounts.referrer.key();
+        referral_account.accrued = 0;
+        referral_account.total_earned = 0;
+        referral_account.total_claimed = 0;
+        referral_account.referred_volume = 0;
+
+        Ok(())
+    }
+
+    /// Claims `code` for the caller. The code resolves to the caller's
+    /// `ReferralAccount` when passed to `buy_and_lock_token` or `leverage_buy`.
+    pub fn register_referral_code(ctx: Context<RegisterReferralCode>, code: String) -> Result<()> {
+        validate_referral_code(&code)?;
+
+        let referral_code = &mut ctx.accounts.referral_code;
+        referral_code.referrer = ctx.accounts.referrer.key();
+        referral_code.code = code;
+
+        Ok(())
+    }
+
+    pub fn claim_referral_rewards(ctx: Context<ClaimReferralRewards>) -> Result<()> {
+        let referral_account = &mut ctx.accounts.referral_account;
+        let amount = referral_account.accrued;
+        require!(amount > 0, CustomError::NothingToClaim);
+
+        referral_account.accrued = 0;
+        referral_account.total_claimed = referral_account
+            .total_claimed
+            .checked_add(amount)
+            .ok_or(CustomError::MathOverflow)?;
+
+        let referral_pool_bump = ctx.bumps.referral_pool;
+        let referral_pool_seeds: &[&[&[u8]]] = &[&[b"referral_pool", &[referral_pool_bump]]];
+
+        token::transfer(
             CpiContext::new_with_signer(
                 ctx.accounts.token_program.to_account_info(),
-                MintTo {
-                    mint: ctx.accounts.token_mint.to_account_info(),
-                    to: ctx.accounts.vault_token_account.to_account_info(),
-                    authority: ctx.accounts.mint_authority.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.referral_pool.to_account_info(),
+                    to: ctx.accounts.referrer_token_account.to_account_info(),
+                    authority: ctx.accounts.referral_pool.to_account_info(),
                 },
-                signer_seeds,
+                referral_pool_seeds,
             ),
-            mintable_tokens,
+            amount,
         )?;
 
-        lock_state.user = ctx.accounts.user.key();
-        lock_state.amount = mintable_tokens;
-        lock_state.unlock_time = clock.unix_timestamp + (lock_hour as i64) * 3600;
-        lock_state.referral = referral;
-        lock_state.initialized = true;
-        lock_state.lock_hour = lock_hour;
-
         Ok(())
     }
 
-    pub fn claim_locked_tokens(ctx: Context<ClaimLockedTokens>) -> Result<()> {
-        let clock = Clock::get()?;
-        let lock_state = &mut ctx.accounts.lock_state;
-
-        require!(lock_state.initialized, CustomError::AlreadyClaimed);
+    pub fn initialize_insurance_fund(ctx: Context<InitializeInsuranceFund>) -> Result<()> {
         require!(
-            clock.unix_timestamp >= lock_state.unlock_time,
-            CustomError::LockPeriodNotOver
+            ctx.accounts.authority.key() == ctx.accounts.metadata.deployer,
+            CustomError::Unauthorized
         );
 
         require!(
-            ctx.accounts.program_payment_token_account.owner == ctx.accounts.pool_authority.key(),
-            CustomError::InvalidOwner
-        );
-        let expected_program_up_usdc_account = anchor_spl::associated_token::get_associated_token_address(
-            &ctx.accounts.up_pool_authority.key(),
-            &ctx.accounts.metadata.up_usdc_mint,
-        );
-        require!(
-            ctx.accounts.program_up_usdc_account.key() == expected_program_up_usdc_account,
-            CustomError::InvalidProgramUpUsdcAccount
+            ctx.accounts.usdc_mint.key() == ctx.accounts.metadata.payment_token,
+            CustomError::InvalidDeployerAccount
         );
 
-        let token_amount = lock_state.amount;
-        let lock_hour = lock_state.lock_hour;
-        let config = get_lock_fee_config(lock_hour);
-        let liquidity_balance_raw =
-            token::accessor::amount(&ctx.accounts.program_up_usdc_account.to_account_info())?
-                as f64;
-        let token_supply_raw = ctx.accounts.token_mint.supply.max(1) as f64;
-        let liquidity_balance = liquidity_balance_raw / 1e6;
-        let token_supply = token_supply_raw / 1e9;
-        let token_amount_dec = token_amount as f64 / 1e9;
-        let price_per_token = liquidity_balance / token_supply;
-        let total_value = token_amount_dec * price_per_token;
-        let total_value_scaled = total_value * 1e6;
-        let founder_fee =
-            ((config.founder_bps as f64 / 10_000.0) * total_value_scaled).round() as u64;
-        let team_fee = ((config.team_bps as f64 / 10_000.0) * total_value_scaled).round() as u64;
-        let liquidity_fee =
-            ((config.liquidity_bps as f64 / 10_000.0) * total_value_scaled).round() as u64;
-        let user_receives =
-            total_value_scaled.round() as u64 - founder_fee - team_fee - liquidity_fee;
-        let vault_bump = ctx.bumps.vault_authority;
-        let vault_seeds: &[&[&[u8]]] =
-            &[&[b"vault", ctx.accounts.user.key.as_ref(), &[vault_bump]]];
+        let expected_insurance_fund_token_account =
+            anchor_spl::associated_token::get_associated_token_address(
+                &ctx.accounts.insurance_authority.key(),
+                &ctx.accounts.usdc_mint.key(),
+            );
+        require!(
+            ctx.accounts.insurance_fund_token_account.key() == expected_insurance_fund_token_account,
+            CustomError::InvalidDeployerAccount
+        );
 
-        token::burn(
-            CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                Burn {
-                    mint: ctx.accounts.token_mint.to_account_info(),
-                    from: ctx.accounts.vault_token_account.to_account_info(),
-                    authority: ctx.accounts.vault_authority.to_account_info(),
+        if ctx.accounts.insurance_fund_token_account.lamports() == 0 {
+            let cpi_ctx = CpiContext::new(
+                ctx.accounts.associated_token_program.to_account_info(),
+                anchor_spl::associated_token::Create {
+                    payer: ctx.accounts.authority.to_account_info(),
+                    associated_token: ctx.accounts.insurance_fund_token_account.to_account_info(),
+                    authority: ctx.accounts.insurance_authority.to_account_info(),
+                    mint: ctx.accounts.usdc_mint.to_account_info(),
+                    system_program: ctx.accounts.system_program.to_account_info(),
+                    token_program: ctx.accounts.token_program.to_account_info(),
                 },
-                vault_seeds,
-            ),
-            token_amount,
-        )?;
+            );
+            anchor_spl::associated_token::create(cpi_ctx)?;
+        }
 
-        let pool_bump = ctx.bumps.pool_authority;
-        let pool_seeds: &[&[&[u8]]] = &[&[
-            b"token_account",
-            ctx.accounts.metadata.payment_token.as_ref(),
-            &[pool_bump],
-        ]];
+        Ok(())
+    }
+
+    /// Deposits payment tokens into the insurance fund. Anyone may contribute.
+    pub fn fund_insurance(ctx: Context<FundInsurance>, amount: u64) -> Result<()> {
+        require!(amount > 0, CustomError::InsufficientAmount);
 
         token::transfer(
-            CpiContext::new_with_signer(
+            CpiContext::new(
                 ctx.accounts.token_program.to_account_info(),
                 Transfer {
-                    from: ctx.accounts.program_payment_token_account.to_account_info(),
-                    to: ctx.accounts.founder_pool_token_account.to_account_info(),
-                    authority: ctx.accounts.pool_authority.to_account_info(),
+                    from: ctx.accounts.funder_usdc_account.to_account_info(),
+                    to: ctx.accounts.insurance_fund_token_account.to_account_info(),
+                    authority: ctx.accounts.funder.to_account_info(),
                 },
-                pool_seeds,
             ),
-            founder_fee,
+            amount,
         )?;
 
-        let pool = &mut ctx.accounts.founders_pool;
-        pool.total_collected += founder_fee;
+        Ok(())
+    }
 
-        token::transfer(
-            CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                Transfer {
-                    from: ctx.accounts.program_payment_token_account.to_account_info(),
-                    to: ctx.accounts.deployer_usdc_account.to_account_info(),
-                    authority: ctx.accounts.pool_authority.to_account_info(),
-                },
-                pool_seeds,
-            ),
-            team_fee,
-        )?;
+    /// Backs up to `amount` of recorded bad debt with real payment tokens moved
+    /// into the pool. The deployer draws from the insurance fund; the team
+    /// covers it out of the team treasury.
+    pub fn cover_bad_debt(
+        ctx: Context<CoverBadDebt>,
+        amount: u64,
+        source: BadDebtSource,
+    ) -> Result<()> {
+        let amount = amount.min(ctx.accounts.protocol_state.bad_debt);
+        require!(amount > 0, CustomError::InsufficientAmount);
+
+        let metadata = &ctx.accounts.metadata;
+        match source {
+            BadDebtSource::InsuranceFund => {
+                require!(
+                    ctx.accounts.authority.key() == metadata.deployer,
+                    CustomError::Unauthorized
+                );
+                require!(
+                    ctx.accounts.source_token_account.key()
+                        == anchor_spl::associated_token::get_associated_token_address(
+                            &ctx.accounts.insurance_authority.key(),
+                            &metadata.payment_token
+                        ),
+                    CustomError::InvalidBadDebtSource
+                );
+
+                let insurance_bump = ctx.bumps.insurance_authority;
+                let insurance_seeds: &[&[&[u8]]] = &[&[b"insurance_fund", &[insurance_bump]]];
+
+                token::transfer(
+                    CpiContext::new_with_signer(
+                        ctx.accounts.token_program.to_account_info(),
+                        Transfer {
+                            from: ctx.accounts.source_token_account.to_account_info(),
+                            to: ctx.accounts.program_payment_token_account.to_account_info(),
+                            authority: ctx.accounts.insurance_authority.to_account_info(),
+                        },
+                        insurance_seeds,
+                    ),
+                    amount,
+                )?;
+            }
+            BadDebtSource::TeamFees => {
+                require!(
+                    ctx.accounts.authority.key() == metadata.team,
+                    CustomError::Unauthorized
+                );
+                let (expected_team_treasury, treasury_bump) =
+                    Pubkey::find_program_address(&[b"team_treasury"], ctx.program_id);
+                require!(
+                    ctx.accounts.source_token_account.key() == expected_team_treasury,
+                    CustomError::InvalidBadDebtSource
+                );
+
+                require!(
+                    amount
+                        <= ctx
+                            .accounts
+                            .source_token_account
+                            .amount
+                            .saturating_sub(ctx.accounts.fee_split.outstanding),
+                    CustomError::InsufficientAmount
+                );
+
+                let treasury_seeds: &[&[&[u8]]] = &[&[b"team_treasury", &[treasury_bump]]];
+
+                token::transfer(
+                    CpiContext::new_with_signer(
+                        ctx.accounts.token_program.to_account_info(),
+                        Transfer {
+                            from: ctx.accounts.source_token_account.to_account_info(),
+                            to: ctx.accounts.program_payment_token_account.to_account_info(),
+                            authority: ctx.accounts.source_token_account.to_account_info(),
+                        },
+                        treasury_seeds,
+                    ),
+                    amount,
+                )?;
+            }
+        }
 
-        token::transfer(
-            CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                Transfer {
-                    from: ctx.accounts.program_payment_token_account.to_account_info(),
-                    to: ctx.accounts.user_usdc_account.to_account_info(),
-                    authority: ctx.accounts.pool_authority.to_account_info(),
-                },
-                pool_seeds,
-            ),
-            user_receives,
-        )?;
+        let protocol_state = &mut ctx.accounts.protocol_state;
+        protocol_state.bad_debt = protocol_state
+            .bad_debt
+            .checked_sub(amount)
+            .ok_or(CustomError::MathUnderflow)?;
 
-        let up_pool_bump = ctx.bumps.up_pool_authority;
-        let up_mint_key = ctx.accounts.up_usdc_mint.key();
-        let up_pool_signer_seeds: &[&[&[u8]]] =
-            &[&[b"token_account", up_mint_key.as_ref(), &[up_pool_bump]]];
+        Ok(())
+    }
 
-        token::burn(
-            CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                Burn {
-                    mint: ctx.accounts.up_usdc_mint.to_account_info(),
-                    from: ctx.accounts.program_up_usdc_account.to_account_info(),
-                    authority: ctx.accounts.up_pool_authority.to_account_info(),
-                },
-                up_pool_signer_seeds,
-            ),
-            user_receives + team_fee + founder_fee,
-        )?;
+    pub fn init_user_vault(_ctx: Context<InitializeUserVault>) -> Result<()> {
+        Ok(())
+    }
 
-        lock_state.initialized = false;
-        lock_state.amount = 0;
+    pub fn init_leverage_vault(
+        _ctx: Context<InitializeLeverageUserVault>,
+        _position_id: u64,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    /// Lets `payer` open locks owned by the caller through `buy_and_lock_token`.
+    pub fn approve_lock_payer(ctx: Context<ApproveLockPayer>, payer: Pubkey) -> Result<()> {
+        let approval = &mut ctx.accounts.approval;
+        approval.beneficiary = ctx.accounts.beneficiary.key();
+        approval.payer = payer;
 
         Ok(())
     }
 
-    pub fn early_unlock_tokens(ctx: Context<EarlyUnlockTokens>) -> Result<()> {
+    pub fn revoke_lock_payer(_ctx: Context<RevokeLockPayer>) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn buy_and_lock_token(
+        ctx: Context<BuyAndLockToken>,
+        amount: u64,
+        lock_seconds: u64,
+        referral: Option<Pubkey>,
+        vesting: bool,
+        referral_code: Option<String>,
+    ) -> Result<()> {
+        let referral = resolve_referral(
+            referral,
+            referral_code.as_deref(),
+            ctx.accounts.referral_code_account.as_deref().map(|account| &**account),
+        )?;
+        require!(
+            ctx.accounts.beneficiary.key() == ctx.accounts.user.key()
+                || ctx.accounts.beneficiary.is_signer
+                || ctx.accounts.payer_approval.is_some(),
+            CustomError::BeneficiaryNotApproved
+        );
+        let clock = Clock::get()?;
         let lock_state = &mut ctx.accounts.lock_state;
+        require!(!lock_state.initialized, CustomError::AlreadyInitialized);
+        validate_lock_seconds(&ctx.accounts.protocol_state, lock_seconds)?;
 
-        require!(lock_state.initialized, CustomError::AlreadyClaimed);
+        require!(ctx.accounts.metadata.initialized, CustomError::AlreadyInitialized);
 
+        let payment_token_mint = ctx.accounts.metadata.payment_token;
         require!(
-            ctx.accounts.program_payment_token_account.owner == ctx.accounts.pool_authority.key(),
+            ctx.accounts.vault_token_account.mint == ctx.accounts.metadata.mint,
+            CustomError::InvalidTokenMint
+        );
+        require!(
+            ctx.accounts.user_usdc_account.mint == payment_token_mint,
+            CustomError::InvalidTokenMint
+        );
+        require!(
+            ctx.accounts.program_payment_token_account.mint == payment_token_mint,
+            CustomError::InvalidTokenMint
+        );
+
+        let (expected_pool_authority, _) = Pubkey::find_program_address(
+            &[b"token_account", ctx.accounts.metadata.payment_token.as_ref()],
+            ctx.program_id,
+        );
+        require!(
+            ctx.accounts.pool_authority.key() == expected_pool_authority,
             CustomError::InvalidOwner
         );
 
-        let token_amount = lock_state.amount;
-        let lock_hour = lock_state.lock_hour;
-        let mut config = get_lock_fee_config(lock_hour);
-        config.team_bps += 50;
-
-        let liquidity_balance_raw =
-            token::accessor::amount(&ctx.accounts.program_up_usdc_account.to_account_info())?
-                as f64;
-        let token_supply_raw = ctx.accounts.token_mint.supply.max(1) as f64;
-        let liquidity_balance = liquidity_balance_raw / 1e6;
-        let token_supply = token_supply_raw / 1e9;
-        let token_amount_dec = token_amount as f64 / 1e9;
-        let price_per_token = liquidity_balance / token_supply;
-        let total_value = token_amount_dec * price_per_token;
-        let total_value_scaled = total_value * 1e6;
-        let founder_fee =
-            ((config.founder_bps as f64 / 10_000.0) * total_value_scaled).round() as u64;
-        let team_fee = ((config.team_bps as f64 / 10_000.0) * total_value_scaled).round() as u64;
-        let liquidity_fee =
-            ((config.liquidity_bps as f64 / 10_000.0) * total_value_scaled).round() as u64;
-        let user_receives =
-            total_value_scaled.round() as u64 - founder_fee - team_fee - liquidity_fee;
-        let vault_bump = ctx.bumps.vault_authority;
-        let vault_seeds: &[&[&[u8]]] =
-            &[&[b"vault", ctx.accounts.user.key.as_ref(), &[vault_bump]]];
+        require!(
+            ctx.accounts.user_up_usdc_account.mint == ctx.accounts.metadata.up_usdc_mint,
+            CustomError::InvalidTokenMint
+        );
+        require!(
+            ctx.accounts.program_up_usdc_account.mint == ctx.accounts.metadata.up_usdc_mint,
+            CustomError::InvalidTokenMint
+        );
+        require!(
+            ctx.accounts.up_usdc_mint.key() == ctx.accounts.metadata.up_usdc_mint,
+            CustomError::InvalidTokenMint
+        );
 
-        token::burn(
-            CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                Burn {
-                    mint: ctx.accounts.token_mint.to_account_info(),
-                    from: ctx.accounts.vault_token_account.to_account_info(),
-                    authority: ctx.accounts.vault_authority.to_account_info(),
-                },
-                vault_seeds,
-            ),
-            token_amount,
-        )?;
+        let expected_founder_pool_token_account = anchor_spl::associated_token::get_associated_token_address(
+            &ctx.accounts.founder_authority.key(),
+            &ctx.accounts.metadata.payment_token,
+        );
+        require!(
+            ctx.accounts.founder_pool_token_account.key() == expected_founder_pool_token_account,
+            CustomError::InvalidFounderPoolTokenAccount
+        );
 
-        let pool_bump = ctx.bumps.pool_authority;
-        let pool_seeds: &[&[&[u8]]] = &[&[
-            b"token_account",
-            ctx.accounts.metadata.payment_token.as_ref(),
-            &[pool_bump],
-        ]];
+        let expected_program_up_usdc_account = anchor_spl::associated_token::get_associated_token_address(
+            &ctx.accounts.up_pool_authority.key(),
+            &ctx.accounts.metadata.up_usdc_mint,
+        );
+        require!(
+            ctx.accounts.program_up_usdc_account.key() == expected_program_up_usdc_account,
+            CustomError::InvalidProgramUpUsdcAccount
+        );
+
+        let config =
+            get_lock_fee_config(lock_seconds, &ctx.accounts.protocol_state.fee_tiers)?;
+        let total_usdc = amount;
+        let team_share = bps_of(total_usdc, config.team_bps)?;
+        let founder_fee = bps_of(total_usdc, config.founder_bps)?;
+        let locked_share = bps_of(total_usdc, config.liquidity_bps)?;
+        let usdc_for_tokens = total_usdc
+            .checked_sub(team_share)
+            .and_then(|v| v.checked_sub(founder_fee))
+            .and_then(|v| v.checked_sub(locked_share))
+            .ok_or(CustomError::MathUnderflow)?;
+
+        let liquidity_balance =
+            token::accessor::amount(&ctx.accounts.program_up_usdc_account.to_account_info())?;
+        let token_supply = ctx.accounts.token_mint.supply;
+
+        let mintable_tokens =
+            quote_mintable_tokens(liquidity_balance, token_supply, usdc_for_tokens, locked_share)?;
+
+        require!(mintable_tokens > 0, CustomError::InsufficientAmount);
+
+        let pool_deposit = usdc_for_tokens
+            .checked_add(locked_share)
+            .ok_or(CustomError::MathOverflow)?;
+
+        pay_team_fee(
+            TeamFeePayment {
+                token_program: ctx.accounts.token_program.to_account_info(),
+                from: ctx.accounts.user_usdc_account.to_account_info(),
+                authority: ctx.accounts.user.to_account_info(),
+                signer_seeds: &[],
+                team_treasury: ctx.accounts.team_treasury.to_account_info(),
+                referral_pool: ctx.accounts.referral_pool.as_ref().map(|pool| pool.to_account_info()),
+                fee_split: &mut ctx.accounts.fee_split,
+                referral_account: ctx
+                    .accounts
+                    .referral_account
+                    .as_deref_mut()
+                    .map(|account| &mut **account),
+            },
+            referral,
+            &ctx.accounts.protocol_state.referral_tiers,
+            team_share,
+            total_usdc,
+        )?;
 
         token::transfer(
-            CpiContext::new_with_signer(
+            CpiContext::new(
                 ctx.accounts.token_program.to_account_info(),
-                Transfer {
-                    from: ctx.accounts.program_payment_token_account.to_account_info(),
+                token::Transfer {
+                    from: ctx.accounts.user_usdc_account.to_account_info(),
                     to: ctx.accounts.founder_pool_token_account.to_account_info(),
-                    authority: ctx.accounts.pool_authority.to_account_info(),
+                    authority: ctx.accounts.user.to_account_info(),
                 },
-                pool_seeds,
             ),
             founder_fee,
         )?;
 
         let pool = &mut ctx.accounts.founders_pool;
-        pool.total_collected += founder_fee;
+        pool.credit(founder_fee)?;
 
         token::transfer(
-            CpiContext::new_with_signer(
+            CpiContext::new(
                 ctx.accounts.token_program.to_account_info(),
                 Transfer {
-                    from: ctx.accounts.program_payment_token_account.to_account_info(),
-                    to: ctx.accounts.deployer_usdc_account.to_account_info(),
-                    authority: ctx.accounts.pool_authority.to_account_info(),
+                    from: ctx.accounts.user_usdc_account.to_account_info(),
+                    to: ctx.accounts.program_payment_token_account.to_account_info(),
+                    authority: ctx.accounts.user.to_account_info(),
                 },
-                pool_seeds,
             ),
-            team_fee,
+            pool_deposit,
         )?;
 
-        token::transfer(
+        let up_usdc_mint_bump = ctx.bumps.up_usdc_mint_authority;
+        let up_usdc_signer_seeds: &[&[&[u8]]] =
+            &[&[b"up_usdc_mint_authority", &[up_usdc_mint_bump]]];
+
+        token::mint_to(
             CpiContext::new_with_signer(
                 ctx.accounts.token_program.to_account_info(),
-                Transfer {
-                    from: ctx.accounts.program_payment_token_account.to_account_info(),
-                    to: ctx.accounts.user_usdc_account.to_account_info(),
-                    authority: ctx.accounts.pool_authority.to_account_info(),
+                token::MintTo {
+                    mint: ctx.accounts.up_usdc_mint.to_account_info(),
+                    to: ctx.accounts.program_up_usdc_account.to_account_info(),
+                    authority: ctx.accounts.up_usdc_mint_authority.to_account_info(),
                 },
-                pool_seeds,
+                up_usdc_signer_seeds,
             ),
-            user_receives,
+            pool_deposit,
         )?;
 
-        let up_pool_bump = ctx.bumps.up_pool_authority;
-        let up_mint_key = ctx.accounts.up_usdc_mint.key();
-        let up_pool_signer_seeds: &[&[&[u8]]] =
-            &[&[b"token_account", up_mint_key.as_ref(), &[up_pool_bump]]];
+        let mint_bump = ctx.bumps.mint_authority;
+        let signer_seeds: &[&[&[u8]]] = &[&[b"mint_authority", &[mint_bump]]];
 
-        token::burn(
+        token::mint_to(
             CpiContext::new_with_signer(
                 ctx.accounts.token_program.to_account_info(),
-                Burn {
-                    mint: ctx.accounts.up_usdc_mint.to_account_info(),
-                    from: ctx.accounts.program_up_usdc_account.to_account_info(),
-                    authority: ctx.accounts.up_pool_authority.to_account_info(),
+                MintTo {
+                    mint: ctx.accounts.token_mint.to_account_info(),
+                    to: ctx.accounts.vault_token_account.to_account_info(),
+                    authority: ctx.accounts.mint_authority.to_account_info(),
                 },
-                up_pool_signer_seeds,
+                signer_seeds,
             ),
-            user_receives + team_fee + founder_fee,
+            mintable_tokens,
         )?;
 
-        lock_state.initialized = false;
-        lock_state.amount = 0;
-
-        Ok(())
-    }
-
-    pub fn add_founder(ctx: Context<AddFounder>, new_founder: Pubkey) -> Result<()> {
-        require!(
-            ctx.accounts.deployer.key() == ctx.accounts.metadata.deployer,
-            CustomError::Unauthorized
-        );
-
-        let pool = &mut ctx.accounts.founders_pool;
-        require!(pool.founder_count < 60, CustomError::FounderLimitReached);
-
-        if pool.founders[..pool.founder_count as usize].contains(&new_founder) {
-            return Err(CustomError::DuplicateFounder.into());
-        }
-
-        let index = pool.founder_count as usize;
-        pool.founders[index] = new_founder;
-        pool.claim_status[index] = 0;
-        pool.founder_count += 1;
-
-        Ok(())
-    }
-
-    pub fn claim_founder_share(ctx: Context<ClaimFounderShare>) -> Result<()> {
-        let pool = &mut ctx.accounts.founders_pool;
-        let founder_key = ctx.accounts.founder.key();
-        let mut index = None;
-
-        for (i, f) in pool.founders.iter().enumerate() {
-            if *f == founder_key {
-                index = Some(i);
-                break;
-            }
-        }
-
-        let idx = index.ok_or(CustomError::NotFounder)?;
-        let total_per_founder = pool.total_collected / 60;
-        let already_claimed = pool.claim_status[idx];
-        let claimable = total_per_founder.saturating_sub(already_claimed);
-
-        require!(claimable > 0, CustomError::NothingToClaim);
-
-        pool.claim_status[idx] += claimable;
-
-        require!(
-            ctx.accounts.founder_token_account.mint == ctx.accounts.founder_pool_token_account.mint,
-            CustomError::InvalidTokenMint
-        );
-
-        let bump = ctx.bumps.founder_authority;
-        let signer_seeds: &[&[&[u8]]] = &[&[b"founder_authority".as_ref(), &[bump]]];
-
-        token::transfer(
-            CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                Transfer {
-                    from: ctx.accounts.founder_pool_token_account.to_account_info(),
-                    to: ctx.accounts.founder_token_account.to_account_info(),
-                    authority: ctx.accounts.founder_authority.to_account_info(),
-                },
-                signer_seeds,
-            ),
-            claimable,
-        )?;
-
-        Ok(())
-    }
-
-    pub fn leverage_buy(
-        ctx: Context<LeverageBuy>,
-        amount: u64,
-        leverage_multiplier: u64,
-        lock_hour: u64,
-        referral: Option<Pubkey>,
-    ) -> Result<()> {
-        require!(
-            matches!(leverage_multiplier, 1..=5),
-            CustomError::InvalidLeverageMultiplier
-        );
-        let clock = Clock::get()?;
-        let leverage_position = &mut ctx.accounts.leverage_position;
-        require!(
-            !leverage_position.initialized,
-            CustomError::AlreadyInitialized
-        );
-
-        require!(
-            matches!(lock_hour, 1 | 2 | 3 | 4 | 6 | 8 | 12),
-            CustomError::InvalidLockPeriod
-        );
-
-        require!(ctx.accounts.metadata.initialized, CustomError::AlreadyInitialized);
-        
-        let payment_token_mint = ctx.accounts.metadata.payment_token;
-        let up_usdc_mint = ctx.accounts.metadata.up_usdc_mint;
-
-        let expected_deployer_usdc_account = anchor_spl::associated_token::get_associated_token_address(
-            &ctx.accounts.metadata.team,
-            &payment_token_mint,
-        );
-        require!(
-            ctx.accounts.deployer_usdc_account.key() == expected_deployer_usdc_account,
-            CustomError::InvalidDeployerUsdcAccount
-        );
-
-        let expected_program_payment_token_account = anchor_spl::associated_token::get_associated_token_address(
-            &ctx.accounts.pool_authority.key(),
-            &payment_token_mint,
-        );
-        require!(
-            ctx.accounts.program_payment_token_account.key() == expected_program_payment_token_account,
-            CustomError::InvalidProgramPaymentTokenAccount
-        );
-
-        let expected_founder_pool_token_account = anchor_spl::associated_token::get_associated_token_address(
-            &ctx.accounts.founder_authority.key(),
-            &payment_token_mint,
-        );
-        require!(
-            ctx.accounts.founder_pool_token_account.key() == expected_founder_pool_token_account,
-            CustomError::InvalidFounderPoolTokenAccount
-        );
-
-        let expected_program_up_usdc_account = anchor_spl::associated_token::get_associated_token_address(
-            &ctx.accounts.up_pool_authority.key(),
-            &up_usdc_mint,
-        );
-        require!(
-            ctx.accounts.program_up_usdc_account.key() == expected_program_up_usdc_account,
-            CustomError::InvalidProgramUpUsdcAccount
-        );
-
-
-        let total_usdc = amount
-            .checked_mul(leverage_multiplier as u64)
-            .ok_or(ProgramError::InvalidArgument)?;
-
-        let borrow_amount = total_usdc - amount;
-
-        let config = get_lock_fee_config(lock_hour);
-
-        let team_share = total_usdc * config.team_bps / 10_000;
-        let founder_fee = total_usdc * config.founder_bps / 10_000;
-        let locked_share = total_usdc * config.liquidity_bps / 10_000;
-        let usdc_for_tokens = total_usdc - team_share - founder_fee - locked_share;
-        let user_amount_after_fees =
-            total_usdc - borrow_amount - team_share - founder_fee - locked_share;
-        let liquidity_balance =
-            token::accessor::amount(&ctx.accounts.program_up_usdc_account.to_account_info())?;
-
-        let token_supply = ctx.accounts.token_mint.supply;
-
-        let mut price_start =
-            (liquidity_balance as u128) * 1_000_000_000 / (token_supply.max(1) as u128);
-        if price_start == 0 {
-            price_start = 1;
-        }
-        let estimated_tokens = (usdc_for_tokens as u128) * 1_000_000_000 / price_start;
-        let liquidity_growth =
-            (liquidity_balance as u128) + (usdc_for_tokens as u128) + (locked_share as u128);
-        let price_end =
-            liquidity_growth * 1_000_000_000 / (((token_supply as u128) + estimated_tokens).max(1));
-        let avg_price = (price_start + price_end) / 2;
-        let mintable_tokens = ((usdc_for_tokens as u128) * 1_000_000_000 / avg_price) as u64;
-
-        require!(mintable_tokens > 0, CustomError::InsufficientAmount);
-
-        if let Some(ref_pubkey) = referral {
-            let referral_token_account = ctx
+        lock_state.position_mint = None;
+        if let Some(position_mint) = ctx.accounts.position_mint.as_ref() {
+            let receipt_token_account = ctx
                 .accounts
-                .referral_usdc_account
+                .receipt_token_account
                 .as_ref()
-                .ok_or(CustomError::MissingReferralAccount)?;
+                .ok_or(CustomError::MissingReceiptAccount)?;
             require!(
-                referral_token_account.owner == ref_pubkey,
-                CustomError::InvalidReferral
+                receipt_token_account.key()
+                    == anchor_spl::associated_token::get_associated_token_address(
+                        &ctx.accounts.beneficiary.key(),
+                        &position_mint.key(),
+                    ),
+                CustomError::InvalidReceipt
             );
 
-            token::transfer(
+            let mint_space = anchor_spl::token::spl_token::state::Mint::LEN;
+            anchor_lang::system_program::create_account(
                 CpiContext::new(
-                    ctx.accounts.token_program.to_account_info(),
-                    Transfer {
-                        from: ctx.accounts.user_usdc_account.to_account_info(),
-                        to: referral_token_account.to_account_info(),
-                        authority: ctx.accounts.user.to_account_info(),
+                    ctx.accounts.system_program.to_account_info(),
+                    anchor_lang::system_program::CreateAccount {
+                        from: ctx.accounts.user.to_account_info(),
+                        to: position_mint.to_account_info(),
                     },
                 ),
-                team_share / 2,
+                ctx.accounts.rent.minimum_balance(mint_space),
+                mint_space as u64,
+                &ctx.accounts.token_program.key(),
             )?;
 
-            token::transfer(
+            token::initialize_mint2(
                 CpiContext::new(
                     ctx.accounts.token_program.to_account_info(),
-                    Transfer {
-                        from: ctx.accounts.user_usdc_account.to_account_info(),
-                        to: ctx.accounts.deployer_usdc_account.to_account_info(),
-                        authority: ctx.accounts.user.to_account_info(),
+                    token::InitializeMint2 {
+                        mint: position_mint.to_account_info(),
                     },
                 ),
-                team_share / 2,
+                0,
+                &ctx.accounts.mint_authority.key(),
+                None,
             )?;
-        } else {
-            token::transfer(
-                CpiContext::new(
+
+            anchor_spl::associated_token::create(CpiContext::new(
+                ctx.accounts.associated_token_program.to_account_info(),
+                anchor_spl::associated_token::Create {
+                    payer: ctx.accounts.user.to_account_info(),
+                    associated_token: receipt_token_account.to_account_info(),
+                    authority: ctx.accounts.beneficiary.to_account_info(),
+                    mint: position_mint.to_account_info(),
+                    system_program: ctx.accounts.system_program.to_account_info(),
+                    token_program: ctx.accounts.token_program.to_account_info(),
+                },
+            ))?;
+
+            token::mint_to(
+                CpiContext::new_with_signer(
                     ctx.accounts.token_program.to_account_info(),
-                    Transfer {
-                        from: ctx.accounts.user_usdc_account.to_account_info(),
-                        to: ctx.accounts.deployer_usdc_account.to_account_info(),
-                        authority: ctx.accounts.user.to_account_info(),
+                    MintTo {
+                        mint: position_mint.to_account_info(),
+                        to: receipt_token_account.to_account_info(),
+                        authority: ctx.accounts.mint_authority.to_account_info(),
                     },
+                    signer_seeds,
                 ),
-                team_share,
+                1,
             )?;
-        }
-
-        token::transfer(
-            CpiContext::new(
-                ctx.accounts.token_program.to_account_info(),
-                token::Transfer {
-                    from: ctx.accounts.user_usdc_account.to_account_info(),
-                    to: ctx.accounts.founder_pool_token_account.to_account_info(),
-                    authority: ctx.accounts.user.to_account_info(),
-                },
-            ),
-            founder_fee,
-        )?;
-        let pool = &mut ctx.accounts.founders_pool;
-        pool.total_collected += founder_fee;
-
-        token::transfer(
-            CpiContext::new(
-                ctx.accounts.token_program.to_account_info(),
-                Transfer {
-                    from: ctx.accounts.user_usdc_account.to_account_info(),
-                    to: ctx.accounts.program_payment_token_account.to_account_info(),
-                    authority: ctx.accounts.user.to_account_info(),
-                },
-            ),
-            user_amount_after_fees + locked_share,
-        )?;
-
-        let up_usdc_mint_bump = ctx.bumps.up_usdc_mint_authority;
-        let up_usdc_signer_seeds: &[&[&[u8]]] =
-            &[&[b"up_usdc_mint_authority", &[up_usdc_mint_bump]]];
-
-        token::mint_to(
-            CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                token::MintTo {
-                    mint: ctx.accounts.up_usdc_mint.to_account_info(),
-                    to: ctx.accounts.program_up_usdc_account.to_account_info(),
-                    authority: ctx.accounts.up_usdc_mint_authority.to_account_info(),
-                },
-                up_usdc_signer_seeds,
-            ),
-            usdc_for_tokens + locked_share,
-        )?;
 
-        let mint_bump = ctx.bumps.mint_authority;
-        let signer_seeds: &[&[&[u8]]] = &[&[b"mint_authority", &[mint_bump]]];
+            token::set_authority(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    token::SetAuthority {
+                        account_or_mint: position_mint.to_account_info(),
+                        current_authority: ctx.accounts.mint_authority.to_account_info(),
+                    },
+                    signer_seeds,
+                ),
+                AuthorityType::MintTokens,
+                None,
+            )?;
 
-        token::mint_to(
-            CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                MintTo {
-                    mint: ctx.accounts.token_mint.to_account_info(),
-                    to: ctx.accounts.vault_token_account.to_account_info(),
-                    authority: ctx.accounts.mint_authority.to_account_info(),
-                },
-                signer_seeds,
-            ),
-            mintable_tokens,
-        )?;
+            lock_state.position_mint = Some(position_mint.key());
+        }
 
-        leverage_position.user = ctx.accounts.user.key();
-        leverage_position.amount_user_paid = amount;
-        leverage_position.amount_borrowed = borrow_amount;
-        leverage_position.unlock_time = clock.unix_timestamp + (lock_hour as i64) * 3600;
-        leverage_position.referral = referral;
-        leverage_position.initialized = true;
-        leverage_position.lock_hour = lock_hour;
-        leverage_position.amount_minted = mintable_tokens;
+        lock_state.user = ctx.accounts.beneficiary.key();
+        lock_state.amount = mintable_tokens;
+        lock_state.unlock_time = unlock_time_after(clock.unix_timestamp, lock_seconds)?;
+        lock_state.vesting = vesting;
+        lock_state.start_time = clock.unix_timestamp;
+        lock_state.end_time = lock_state.unlock_time;
+        lock_state.claimed_amount = 0;
+        lock_state.referral = referral;
+        lock_state.initialized = true;
+        lock_state.lock_seconds = lock_seconds;
+        lock_state.auto_settle = false;
+        lock_state.auto_rollover = false;
+        lock_state.version = LOCK_STATE_VERSION;
+        lock_state.fee_config = config;
 
         Ok(())
     }
 
-    pub fn leverage_sell(ctx: Context<LeverageSell>) -> Result<()> {
+    pub fn claim_locked_tokens(ctx: Context<ClaimLockedTokens>) -> Result<()> {
         let clock = Clock::get()?;
-        let position = &mut ctx.accounts.leverage_position;
-        require!(position.initialized, CustomError::AlreadyClaimed);
+        let lock_state = &mut ctx.accounts.lock_state;
+
+        require!(lock_state.initialized, CustomError::AlreadyClaimed);
         require!(
-            clock.unix_timestamp >= position.unlock_time,
+            clock.unix_timestamp >= lock_state.unlock_time,
             CustomError::LockPeriodNotOver
         );
+        authorize_lock_holder(
+            lock_state,
+            ctx.accounts.user.key,
+            ctx.accounts.holder.key,
+            ctx.accounts.receipt_token_account.as_deref(),
+        )?;
 
         require!(
             ctx.accounts.program_payment_token_account.owner == ctx.accounts.pool_authority.key(),
             CustomError::InvalidOwner
         );
-       
-        let amount_minted = position.amount_minted;
-        let liquidity_balance_raw =
-            token::accessor::amount(&ctx.accounts.program_up_usdc_account.to_account_info())?
-                as f64;
-        let token_supply_raw = ctx.accounts.token_mint.supply.max(1) as f64;
-        let liquidity_balance = liquidity_balance_raw / 1e6;
-        let token_supply = token_supply_raw / 1e9;
-        let price_per_token = liquidity_balance / token_supply;
-        let token_amount_dec = amount_minted as f64 / 1e9;
-        let total_value = token_amount_dec * price_per_token;
-        let total_value_scaled = total_value * 1e6;
-        let borrowed = position.amount_borrowed;
-
-        let lock_hour = position.lock_hour;
-        let config = get_lock_fee_config(lock_hour);
-
-        let founder_fee =
-            ((config.founder_bps as f64 / 10_000.0) * total_value_scaled).round() as u64;
-        let team_fee = ((config.team_bps as f64 / 10_000.0) * total_value_scaled).round() as u64;
-        let liquidity_fee =
-            ((config.liquidity_bps as f64 / 10_000.0) * total_value_scaled).round() as u64;
-        let user_cut = total_value_scaled.round() as u64
-            - borrowed as u64
-            - founder_fee
-            - team_fee
-            - liquidity_fee;
+        let expected_program_up_usdc_account = anchor_spl::associated_token::get_associated_token_address(
+            &ctx.accounts.up_pool_authority.key(),
+            &ctx.accounts.metadata.up_usdc_mint,
+        );
+        require!(
+            ctx.accounts.program_up_usdc_account.key() == expected_program_up_usdc_account,
+            CustomError::InvalidProgramUpUsdcAccount
+        );
 
+        let token_amount = lock_state.amount;
+        let config = lock_state.fee_config.clone();
+        let liquidity_balance =
+            token::accessor::amount(&ctx.accounts.program_up_usdc_account.to_account_info())?;
+        let quote = quote_exit(
+            liquidity_balance,
+            ctx.accounts.token_mint.supply,
+            token_amount,
+            &config,
+        )?;
+        let founder_fee = quote.founder_fee;
+        let team_fee = quote.team_fee;
+        let user_receives = quote.after_fees()?;
         let vault_bump = ctx.bumps.vault_authority;
         let vault_seeds: &[&[&[u8]]] =
-            &[&[b"l_vault", ctx.accounts.user.key.as_ref(), &[vault_bump]]];
+            &[&[b"vault", ctx.accounts.user.key.as_ref(), &[vault_bump]]];
+
         token::burn(
             CpiContext::new_with_signer(
                 ctx.accounts.token_program.to_account_info(),
@@ -1013,25 +1106,7 @@ pub mod up_only {
                 },
                 vault_seeds,
             ),
-            amount_minted,
-        )?;
-
-        let up_pool_bump = ctx.bumps.up_pool_authority;
-        let up_mint_key = ctx.accounts.up_usdc_mint.key();
-        let up_pool_signer_seeds: &[&[&[u8]]] =
-            &[&[b"token_account", up_mint_key.as_ref(), &[up_pool_bump]]];
-
-        token::burn(
-            CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                token::Burn {
-                    mint: ctx.accounts.up_usdc_mint.to_account_info(),
-                    from: ctx.accounts.program_up_usdc_account.to_account_info(),
-                    authority: ctx.accounts.up_pool_authority.to_account_info(),
-                },
-                up_pool_signer_seeds,
-            ),
-            user_cut + team_fee + founder_fee + borrowed as u64,
+            token_amount,
         )?;
 
         let pool_bump = ctx.bumps.pool_authority;
@@ -1055,94 +1130,116 @@ pub mod up_only {
         )?;
 
         let pool = &mut ctx.accounts.founders_pool;
-        pool.total_collected += founder_fee;
-
-        if let Some(ref_pubkey) = position.referral {
-            let referral_token_account = ctx
+        pool.credit(founder_fee)?;
+
+        pay_team_fee(
+            TeamFeePayment {
+                token_program: ctx.accounts.token_program.to_account_info(),
+                from: ctx.accounts.program_payment_token_account.to_account_info(),
+                authority: ctx.accounts.pool_authority.to_account_info(),
+                signer_seeds: pool_seeds,
+                team_treasury: ctx.accounts.team_treasury.to_account_info(),
+                referral_pool: ctx.accounts.referral_pool.as_ref().map(|pool| pool.to_account_info()),
+                fee_split: &mut ctx.accounts.fee_split,
+                referral_account: ctx
+                    .accounts
+                    .referral_account
+                    .as_deref_mut()
+                    .map(|account| &mut **account),
+            },
+            lock_state.referral,
+            &ctx.accounts.protocol_state.referral_tiers,
+            team_fee,
+            0,
+        )?;
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.program_payment_token_account.to_account_info(),
+                    to: ctx.accounts.user_usdc_account.to_account_info(),
+                    authority: ctx.accounts.pool_authority.to_account_info(),
+                },
+                pool_seeds,
+            ),
+            user_receives,
+        )?;
+
+        let up_pool_bump = ctx.bumps.up_pool_authority;
+        let up_mint_key = ctx.accounts.up_usdc_mint.key();
+        let up_pool_signer_seeds: &[&[&[u8]]] =
+            &[&[b"token_account", up_mint_key.as_ref(), &[up_pool_bump]]];
+
+        token::burn(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Burn {
+                    mint: ctx.accounts.up_usdc_mint.to_account_info(),
+                    from: ctx.accounts.program_up_usdc_account.to_account_info(),
+                    authority: ctx.accounts.up_pool_authority.to_account_info(),
+                },
+                up_pool_signer_seeds,
+            ),
+            user_receives
+                .checked_add(team_fee)
+                .and_then(|v| v.checked_add(founder_fee))
+                .ok_or(CustomError::MathOverflow)?,
+        )?;
+
+        if let Some(position_mint) = lock_state.position_mint {
+            let receipt_mint = ctx
+                .accounts
+                .position_mint
+                .as_ref()
+                .ok_or(CustomError::MissingReceiptAccount)?;
+            let receipt_token_account = ctx
                 .accounts
-                .referral_usdc_account
+                .receipt_token_account
                 .as_ref()
-                .ok_or(CustomError::MissingReferralAccount)?;
+                .ok_or(CustomError::MissingReceiptAccount)?;
             require!(
-                referral_token_account.owner == ref_pubkey,
-                CustomError::InvalidReferral
+                receipt_mint.key() == position_mint,
+                CustomError::InvalidReceipt
             );
 
-            let referral_share = team_fee / 2;
-            let deployer_share = team_fee - referral_share;
-
-            token::transfer(
-                CpiContext::new_with_signer(
-                    ctx.accounts.token_program.to_account_info(),
-                    Transfer {
-                        from: ctx.accounts.program_payment_token_account.to_account_info(),
-                        to: referral_token_account.to_account_info(),
-                        authority: ctx.accounts.pool_authority.to_account_info(),
-                    },
-                    pool_seeds,
-                ),
-                referral_share,
-            )?;
-
-            token::transfer(
-                CpiContext::new_with_signer(
-                    ctx.accounts.token_program.to_account_info(),
-                    Transfer {
-                        from: ctx.accounts.program_payment_token_account.to_account_info(),
-                        to: ctx.accounts.deployer_usdc_account.to_account_info(),
-                        authority: ctx.accounts.pool_authority.to_account_info(),
-                    },
-                    pool_seeds,
-                ),
-                deployer_share,
-            )?;
-        } else {
-            token::transfer(
-                CpiContext::new_with_signer(
-                    ctx.accounts.token_program.to_account_info(),
-                    Transfer {
-                        from: ctx.accounts.program_payment_token_account.to_account_info(),
-                        to: ctx.accounts.deployer_usdc_account.to_account_info(),
-                        authority: ctx.accounts.pool_authority.to_account_info(),
-                    },
-                    pool_seeds,
-                ),
-                team_fee,
-            )?;
-        }
-
-        if user_cut > 0 {
-            token::transfer(
-                CpiContext::new_with_signer(
+            token::burn(
+                CpiContext::new(
                     ctx.accounts.token_program.to_account_info(),
-                    Transfer {
-                        from: ctx.accounts.program_payment_token_account.to_account_info(),
-                        to: ctx.accounts.user_usdc_account.to_account_info(),
-                        authority: ctx.accounts.pool_authority.to_account_info(),
+                    Burn {
+                        mint: receipt_mint.to_account_info(),
+                        from: receipt_token_account.to_account_info(),
+                        authority: ctx.accounts.holder.to_account_info(),
                     },
-                    pool_seeds,
                 ),
-                user_cut,
+                1,
             )?;
+            lock_state.position_mint = None;
         }
 
-        position.initialized = false;
-        position.amount_minted = 0;
-        position.amount_borrowed = 0;
-        position.amount_user_paid = 0;
+        lock_state.initialized = false;
+        lock_state.amount = 0;
 
         Ok(())
     }
 
-    pub fn early_close_leverage(ctx: Context<EarlyCloseLeverage>) -> Result<()> {
-        let leverage_position = &mut ctx.accounts.leverage_position;
-        require!(leverage_position.initialized, CustomError::AlreadyClaimed);
+    pub fn claim_vested(ctx: Context<ClaimLockedTokens>) -> Result<()> {
+        let clock = Clock::get()?;
+        let lock_state = &mut ctx.accounts.lock_state;
+
+        require!(lock_state.initialized, CustomError::AlreadyClaimed);
+        require!(lock_state.vesting, CustomError::NotVestingLock);
+        authorize_lock_holder(
+            lock_state,
+            ctx.accounts.user.key,
+            ctx.accounts.holder.key,
+            ctx.accounts.receipt_token_account.as_deref(),
+        )?;
 
         require!(
             ctx.accounts.program_payment_token_account.owner == ctx.accounts.pool_authority.key(),
             CustomError::InvalidOwner
         );
-
         let expected_program_up_usdc_account = anchor_spl::associated_token::get_associated_token_address(
             &ctx.accounts.up_pool_authority.key(),
             &ctx.accounts.metadata.up_usdc_mint,
@@ -1152,41 +1249,37 @@ pub mod up_only {
             CustomError::InvalidProgramUpUsdcAccount
         );
 
-        let amount_minted = leverage_position.amount_minted;
-        let lock_hour = leverage_position.lock_hour;
-        let mut config = get_lock_fee_config(lock_hour);
-        config.team_bps += 50; // Add 0.5% penalty
-
-        let borrowed = leverage_position.amount_borrowed;
-
-        let liquidity_balance_raw =
-            token::accessor::amount(&ctx.accounts.program_up_usdc_account.to_account_info())?
-                as f64;
-        let token_supply_raw = ctx.accounts.token_mint.supply.max(1) as f64;
-
-        let liquidity_balance = liquidity_balance_raw / 1e6;
-        let token_supply = token_supply_raw / 1e9;
-        let token_amount_dec = amount_minted as f64 / 1e9;
-
-        let price_per_token = liquidity_balance / token_supply;
-        let total_value = token_amount_dec * price_per_token;
-        let total_value_scaled = total_value * 1e6;
-
-        let founder_fee =
-            ((config.founder_bps as f64 / 10_000.0) * total_value_scaled).round() as u64;
-        let team_fee = ((config.team_bps as f64 / 10_000.0) * total_value_scaled).round() as u64;
-        let liquidity_fee =
-            ((config.liquidity_bps as f64 / 10_000.0) * total_value_scaled).round() as u64;
-
-        let user_cut = total_value_scaled.round() as u64
-            - borrowed as u64
-            - founder_fee
-            - team_fee
-            - liquidity_fee;
+        let total_locked = lock_state
+            .amount
+            .checked_add(lock_state.claimed_amount)
+            .ok_or(CustomError::MathOverflow)?;
+        let vested = vested_amount(
+            total_locked,
+            lock_state.start_time,
+            lock_state.end_time,
+            clock.unix_timestamp,
+        )?;
+        let token_amount = vested
+            .checked_sub(lock_state.claimed_amount)
+            .ok_or(CustomError::MathUnderflow)?;
+        require!(token_amount > 0, CustomError::NothingToClaim);
 
+        let config = lock_state.fee_config.clone();
+        let liquidity_balance =
+            token::accessor::amount(&ctx.accounts.program_up_usdc_account.to_account_info())?;
+        let quote = quote_exit(
+            liquidity_balance,
+            ctx.accounts.token_mint.supply,
+            token_amount,
+            &config,
+        )?;
+        let founder_fee = quote.founder_fee;
+        let team_fee = quote.team_fee;
+        let user_receives = quote.after_fees()?;
         let vault_bump = ctx.bumps.vault_authority;
         let vault_seeds: &[&[&[u8]]] =
-            &[&[b"l_vault", ctx.accounts.user.key.as_ref(), &[vault_bump]]];
+            &[&[b"vault", ctx.accounts.user.key.as_ref(), &[vault_bump]]];
+
         token::burn(
             CpiContext::new_with_signer(
                 ctx.accounts.token_program.to_account_info(),
@@ -1197,7 +1290,64 @@ pub mod up_only {
                 },
                 vault_seeds,
             ),
-            amount_minted,
+            token_amount,
+        )?;
+
+        let pool_bump = ctx.bumps.pool_authority;
+        let pool_seeds: &[&[&[u8]]] = &[&[
+            b"token_account",
+            ctx.accounts.metadata.payment_token.as_ref(),
+            &[pool_bump],
+        ]];
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.program_payment_token_account.to_account_info(),
+                    to: ctx.accounts.founder_pool_token_account.to_account_info(),
+                    authority: ctx.accounts.pool_authority.to_account_info(),
+                },
+                pool_seeds,
+            ),
+            founder_fee,
+        )?;
+
+        let pool = &mut ctx.accounts.founders_pool;
+        pool.credit(founder_fee)?;
+
+        pay_team_fee(
+            TeamFeePayment {
+                token_program: ctx.accounts.token_program.to_account_info(),
+                from: ctx.accounts.program_payment_token_account.to_account_info(),
+                authority: ctx.accounts.pool_authority.to_account_info(),
+                signer_seeds: pool_seeds,
+                team_treasury: ctx.accounts.team_treasury.to_account_info(),
+                referral_pool: ctx.accounts.referral_pool.as_ref().map(|pool| pool.to_account_info()),
+                fee_split: &mut ctx.accounts.fee_split,
+                referral_account: ctx
+                    .accounts
+                    .referral_account
+                    .as_deref_mut()
+                    .map(|account| &mut **account),
+            },
+            lock_state.referral,
+            &ctx.accounts.protocol_state.referral_tiers,
+            team_fee,
+            0,
+        )?;
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.program_payment_token_account.to_account_info(),
+                    to: ctx.accounts.user_usdc_account.to_account_info(),
+                    authority: ctx.accounts.pool_authority.to_account_info(),
+                },
+                pool_seeds,
+            ),
+            user_receives,
         )?;
 
         let up_pool_bump = ctx.bumps.up_pool_authority;
@@ -1208,14 +1358,185 @@ pub mod up_only {
         token::burn(
             CpiContext::new_with_signer(
                 ctx.accounts.token_program.to_account_info(),
-                token::Burn {
+                Burn {
                     mint: ctx.accounts.up_usdc_mint.to_account_info(),
                     from: ctx.accounts.program_up_usdc_account.to_account_info(),
                     authority: ctx.accounts.up_pool_authority.to_account_info(),
                 },
                 up_pool_signer_seeds,
             ),
-            user_cut + team_fee + founder_fee + borrowed as u64,
+            user_receives
+                .checked_add(team_fee)
+                .and_then(|v| v.checked_add(founder_fee))
+                .ok_or(CustomError::MathOverflow)?,
+        )?;
+
+        lock_state.claimed_amount = vested;
+        lock_state.amount = lock_state
+            .amount
+            .checked_sub(token_amount)
+            .ok_or(CustomError::MathUnderflow)?;
+
+        if lock_state.amount == 0 {
+            if let Some(position_mint) = lock_state.position_mint {
+                let receipt_mint = ctx
+                    .accounts
+                    .position_mint
+                    .as_ref()
+                    .ok_or(CustomError::MissingReceiptAccount)?;
+                let receipt_token_account = ctx
+                    .accounts
+                    .receipt_token_account
+                    .as_ref()
+                    .ok_or(CustomError::MissingReceiptAccount)?;
+                require!(
+                    receipt_mint.key() == position_mint,
+                    CustomError::InvalidReceipt
+                );
+
+                token::burn(
+                    CpiContext::new(
+                        ctx.accounts.token_program.to_account_info(),
+                        Burn {
+                            mint: receipt_mint.to_account_info(),
+                            from: receipt_token_account.to_account_info(),
+                            authority: ctx.accounts.holder.to_account_info(),
+                        },
+                    ),
+                    1,
+                )?;
+                lock_state.position_mint = None;
+            }
+
+            lock_state.initialized = false;
+        }
+
+        Ok(())
+    }
+
+    pub fn set_auto_settle(ctx: Context<SetLockOptions>, enabled: bool) -> Result<()> {
+        let lock_state = &mut ctx.accounts.lock_state;
+        require!(lock_state.initialized, CustomError::AlreadyClaimed);
+        require!(
+            lock_state.position_mint.is_none(),
+            CustomError::ReceiptPositionUnsupported
+        );
+
+        require!(
+            !(enabled && lock_state.auto_rollover),
+            CustomError::ConflictingLockOptions
+        );
+
+        lock_state.auto_settle = enabled;
+
+        Ok(())
+    }
+
+    pub fn set_auto_rollover(ctx: Context<SetLockOptions>, enabled: bool) -> Result<()> {
+        let lock_state = &mut ctx.accounts.lock_state;
+        require!(lock_state.initialized, CustomError::AlreadyClaimed);
+        require!(
+            lock_state.position_mint.is_none(),
+            CustomError::ReceiptPositionUnsupported
+        );
+        require!(
+            !(enabled && lock_state.auto_settle),
+            CustomError::ConflictingLockOptions
+        );
+
+        lock_state.auto_rollover = enabled;
+
+        Ok(())
+    }
+
+    /// Relocks a matured position for `lock_seconds` without unwinding it. Only
+    /// the reduced rollover fee is taken out of the vault; the rest of the UP
+    /// stays locked. Anyone may roll an `auto_rollover` position into its
+    /// current tier at the fees it was opened with and earns the keeper tip
+    /// for doing so. An owner rollover takes the current fee schedule.
+    pub fn rollover(ctx: Context<Rollover>, lock_seconds: u64) -> Result<()> {
+        let clock = Clock::get()?;
+        let lock_state = &mut ctx.accounts.lock_state;
+
+        require!(lock_state.initialized, CustomError::AlreadyClaimed);
+        require!(
+            clock.unix_timestamp >= lock_state.unlock_time,
+            CustomError::LockPeriodNotOver
+        );
+        validate_lock_seconds(&ctx.accounts.protocol_state, lock_seconds)?;
+
+        // Supplying a receipt or signing as the lock owner claims an owner
+        // rollover, which must then pass the holder check; anyone else is
+        // treated as a keeper.
+        let by_owner = ctx.accounts.receipt_token_account.is_some()
+            || ctx.accounts.authority.key() == lock_state.user;
+        if by_owner {
+            authorize_lock_holder(
+                lock_state,
+                ctx.accounts.user.key,
+                ctx.accounts.authority.key,
+                ctx.accounts.receipt_token_account.as_deref(),
+            )?;
+        } else {
+            require!(lock_state.auto_rollover, CustomError::Unauthorized);
+            require!(
+                lock_seconds == lock_state.lock_seconds,
+                CustomError::InvalidLockPeriod
+            );
+        }
+
+        let token_amount = lock_state.amount;
+        let entry_config = if by_owner {
+            get_lock_fee_config(lock_seconds, &ctx.accounts.protocol_state.fee_tiers)?
+        } else {
+            lock_state.fee_config.clone()
+        };
+        let config = rollover_fee_config(&entry_config);
+        let total_fee_bps = config
+            .liquidity_bps
+            .checked_add(config.team_bps)
+            .and_then(|v| v.checked_add(config.founder_bps))
+            .ok_or(CustomError::MathOverflow)?;
+        let fee_tokens = bps_of(token_amount, total_fee_bps)?;
+        let remaining_tokens = token_amount
+            .checked_sub(fee_tokens)
+            .ok_or(CustomError::MathUnderflow)?;
+        require!(remaining_tokens > 0, CustomError::InsufficientAmount);
+
+        let liquidity_balance =
+            token::accessor::amount(&ctx.accounts.program_up_usdc_account.to_account_info())?;
+        let quote = quote_exit(
+            liquidity_balance,
+            ctx.accounts.token_mint.supply,
+            token_amount,
+            &config,
+        )?;
+        let founder_fee = quote.founder_fee;
+        let keeper_tip = if by_owner {
+            0
+        } else {
+            bps_of(quote.team_fee, KEEPER_TIP_BPS)?
+        };
+        let team_fee = quote
+            .team_fee
+            .checked_sub(keeper_tip)
+            .ok_or(CustomError::MathUnderflow)?;
+
+        let vault_bump = ctx.bumps.vault_authority;
+        let vault_seeds: &[&[&[u8]]] =
+            &[&[b"vault", ctx.accounts.user.key.as_ref(), &[vault_bump]]];
+
+        token::burn(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Burn {
+                    mint: ctx.accounts.token_mint.to_account_info(),
+                    from: ctx.accounts.vault_token_account.to_account_info(),
+                    authority: ctx.accounts.vault_authority.to_account_info(),
+                },
+                vault_seeds,
+            ),
+            fee_tokens,
         )?;
 
         let pool_bump = ctx.bumps.pool_authority;
@@ -1239,245 +1560,3875 @@ pub mod up_only {
         )?;
 
         let pool = &mut ctx.accounts.founders_pool;
-        pool.total_collected += founder_fee;
+        pool.credit(founder_fee)?;
+
+        pay_team_fee(
+            TeamFeePayment {
+                token_program: ctx.accounts.token_program.to_account_info(),
+                from: ctx.accounts.program_payment_token_account.to_account_info(),
+                authority: ctx.accounts.pool_authority.to_account_info(),
+                signer_seeds: pool_seeds,
+                team_treasury: ctx.accounts.team_treasury.to_account_info(),
+                referral_pool: ctx.accounts.referral_pool.as_ref().map(|pool| pool.to_account_info()),
+                fee_split: &mut ctx.accounts.fee_split,
+                referral_account: ctx
+                    .accounts
+                    .referral_account
+                    .as_deref_mut()
+                    .map(|account| &mut **account),
+            },
+            lock_state.referral,
+            &ctx.accounts.protocol_state.referral_tiers,
+            team_fee,
+            0,
+        )?;
 
-        if let Some(ref_pubkey) = leverage_position.referral {
-            let referral_token_account = ctx
+        if keeper_tip > 0 {
+            let keeper_token_account = ctx
                 .accounts
-                .referral_usdc_account
+                .keeper_usdc_account
                 .as_ref()
-                .ok_or(CustomError::MissingReferralAccount)?;
+                .ok_or(CustomError::MissingKeeperAccount)?;
             require!(
-                referral_token_account.owner == ref_pubkey,
-                CustomError::InvalidReferral
+                keeper_token_account.owner == ctx.accounts.authority.key(),
+                CustomError::InvalidOwner
             );
 
-            let referral_share = team_fee / 2;
-            let deployer_share = team_fee - referral_share;
+            token::transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: ctx.accounts.program_payment_token_account.to_account_info(),
+                        to: keeper_token_account.to_account_info(),
+                        authority: ctx.accounts.pool_authority.to_account_info(),
+                    },
+                    pool_seeds,
+                ),
+                keeper_tip,
+            )?;
+        }
+
+        let up_pool_bump = ctx.bumps.up_pool_authority;
+        let up_mint_key = ctx.accounts.up_usdc_mint.key();
+        let up_pool_signer_seeds: &[&[&[u8]]] =
+            &[&[b"token_account", up_mint_key.as_ref(), &[up_pool_bump]]];
+
+        token::burn(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Burn {
+                    mint: ctx.accounts.up_usdc_mint.to_account_info(),
+                    from: ctx.accounts.program_up_usdc_account.to_account_info(),
+                    authority: ctx.accounts.up_pool_authority.to_account_info(),
+                },
+                up_pool_signer_seeds,
+            ),
+            quote
+                .team_fee
+                .checked_add(founder_fee)
+                .ok_or(CustomError::MathOverflow)?,
+        )?;
+
+        lock_state.amount = remaining_tokens;
+        lock_state.lock_seconds = lock_seconds;
+        lock_state.fee_config = entry_config;
+        lock_state.unlock_time = unlock_time_after(clock.unix_timestamp, lock_seconds)?;
+        lock_state.start_time = clock.unix_timestamp;
+        lock_state.end_time = lock_state.unlock_time;
+        lock_state.claimed_amount = 0;
+
+        Ok(())
+    }
+
+    pub fn crank_settle(ctx: Context<CrankSettle>) -> Result<()> {
+        let clock = Clock::get()?;
+        let lock_state = &mut ctx.accounts.lock_state;
+
+        require!(lock_state.initialized, CustomError::AlreadyClaimed);
+        require!(lock_state.auto_settle, CustomError::AutoSettleDisabled);
+        require!(
+            clock.unix_timestamp >= lock_state.unlock_time,
+            CustomError::LockPeriodNotOver
+        );
+
+        let token_amount = lock_state.amount;
+        let config = lock_state.fee_config.clone();
+        let liquidity_balance =
+            token::accessor::amount(&ctx.accounts.program_up_usdc_account.to_account_info())?;
+        let quote = quote_exit(
+            liquidity_balance,
+            ctx.accounts.token_mint.supply,
+            token_amount,
+            &config,
+        )?;
+        let founder_fee = quote.founder_fee;
+        let keeper_tip = bps_of(quote.team_fee, KEEPER_TIP_BPS)?;
+        let team_fee = quote
+            .team_fee
+            .checked_sub(keeper_tip)
+            .ok_or(CustomError::MathUnderflow)?;
+        let user_receives = quote.after_fees()?;
+
+        let vault_bump = ctx.bumps.vault_authority;
+        let vault_seeds: &[&[&[u8]]] =
+            &[&[b"vault", ctx.accounts.user.key.as_ref(), &[vault_bump]]];
+
+        token::burn(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Burn {
+                    mint: ctx.accounts.token_mint.to_account_info(),
+                    from: ctx.accounts.vault_token_account.to_account_info(),
+                    authority: ctx.accounts.vault_authority.to_account_info(),
+                },
+                vault_seeds,
+            ),
+            token_amount,
+        )?;
+
+        let pool_bump = ctx.bumps.pool_authority;
+        let pool_seeds: &[&[&[u8]]] = &[&[
+            b"token_account",
+            ctx.accounts.metadata.payment_token.as_ref(),
+            &[pool_bump],
+        ]];
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.program_payment_token_account.to_account_info(),
+                    to: ctx.accounts.founder_pool_token_account.to_account_info(),
+                    authority: ctx.accounts.pool_authority.to_account_info(),
+                },
+                pool_seeds,
+            ),
+            founder_fee,
+        )?;
+
+        let pool = &mut ctx.accounts.founders_pool;
+        pool.credit(founder_fee)?;
+
+        pay_team_fee(
+            TeamFeePayment {
+                token_program: ctx.accounts.token_program.to_account_info(),
+                from: ctx.accounts.program_payment_token_account.to_account_info(),
+                authority: ctx.accounts.pool_authority.to_account_info(),
+                signer_seeds: pool_seeds,
+                team_treasury: ctx.accounts.team_treasury.to_account_info(),
+                referral_pool: ctx.accounts.referral_pool.as_ref().map(|pool| pool.to_account_info()),
+                fee_split: &mut ctx.accounts.fee_split,
+                referral_account: ctx
+                    .accounts
+                    .referral_account
+                    .as_deref_mut()
+                    .map(|account| &mut **account),
+            },
+            lock_state.referral,
+            &ctx.accounts.protocol_state.referral_tiers,
+            team_fee,
+            0,
+        )?;
+
+        if keeper_tip > 0 {
+            token::transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: ctx.accounts.program_payment_token_account.to_account_info(),
+                        to: ctx.accounts.keeper_usdc_account.to_account_info(),
+                        authority: ctx.accounts.pool_authority.to_account_info(),
+                    },
+                    pool_seeds,
+                ),
+                keeper_tip,
+            )?;
+        }
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.program_payment_token_account.to_account_info(),
+                    to: ctx.accounts.user_usdc_account.to_account_info(),
+                    authority: ctx.accounts.pool_authority.to_account_info(),
+                },
+                pool_seeds,
+            ),
+            user_receives,
+        )?;
+
+        let up_pool_bump = ctx.bumps.up_pool_authority;
+        let up_mint_key = ctx.accounts.up_usdc_mint.key();
+        let up_pool_signer_seeds: &[&[&[u8]]] =
+            &[&[b"token_account", up_mint_key.as_ref(), &[up_pool_bump]]];
+
+        token::burn(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Burn {
+                    mint: ctx.accounts.up_usdc_mint.to_account_info(),
+                    from: ctx.accounts.program_up_usdc_account.to_account_info(),
+                    authority: ctx.accounts.up_pool_authority.to_account_info(),
+                },
+                up_pool_signer_seeds,
+            ),
+            user_receives
+                .checked_add(quote.team_fee)
+                .and_then(|v| v.checked_add(founder_fee))
+                .ok_or(CustomError::MathOverflow)?,
+        )?;
+
+        lock_state.initialized = false;
+        lock_state.amount = 0;
+
+        Ok(())
+    }
+
+    pub fn early_unlock_tokens(ctx: Context<EarlyUnlockTokens>) -> Result<()> {
+        let lock_state = &mut ctx.accounts.lock_state;
+
+        require!(lock_state.initialized, CustomError::AlreadyClaimed);
+        authorize_lock_holder(
+            lock_state,
+            ctx.accounts.user.key,
+            ctx.accounts.holder.key,
+            ctx.accounts.receipt_token_account.as_deref(),
+        )?;
+
+        require!(
+            ctx.accounts.program_payment_token_account.owner == ctx.accounts.pool_authority.key(),
+            CustomError::InvalidOwner
+        );
+
+        let token_amount = lock_state.amount;
+        let lock_seconds = lock_state.lock_seconds;
+        let mut config = lock_state.fee_config.clone();
+        apply_early_penalty(
+            &mut config,
+            &ctx.accounts.protocol_state,
+            lock_state.unlock_time,
+            lock_seconds,
+            Clock::get()?.unix_timestamp,
+        )?;
+
+        let liquidity_balance =
+            token::accessor::amount(&ctx.accounts.program_up_usdc_account.to_account_info())?;
+        let quote = quote_exit(
+            liquidity_balance,
+            ctx.accounts.token_mint.supply,
+            token_amount,
+            &config,
+        )?;
+        let founder_fee = quote.founder_fee;
+        let team_fee = quote.team_fee;
+        let user_receives = quote.after_fees()?;
+        let vault_bump = ctx.bumps.vault_authority;
+        let vault_seeds: &[&[&[u8]]] =
+            &[&[b"vault", ctx.accounts.user.key.as_ref(), &[vault_bump]]];
+
+        token::burn(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Burn {
+                    mint: ctx.accounts.token_mint.to_account_info(),
+                    from: ctx.accounts.vault_token_account.to_account_info(),
+                    authority: ctx.accounts.vault_authority.to_account_info(),
+                },
+                vault_seeds,
+            ),
+            token_amount,
+        )?;
+
+        let pool_bump = ctx.bumps.pool_authority;
+        let pool_seeds: &[&[&[u8]]] = &[&[
+            b"token_account",
+            ctx.accounts.metadata.payment_token.as_ref(),
+            &[pool_bump],
+        ]];
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.program_payment_token_account.to_account_info(),
+                    to: ctx.accounts.founder_pool_token_account.to_account_info(),
+                    authority: ctx.accounts.pool_authority.to_account_info(),
+                },
+                pool_seeds,
+            ),
+            founder_fee,
+        )?;
+
+        let pool = &mut ctx.accounts.founders_pool;
+        pool.credit(founder_fee)?;
+
+        pay_team_fee(
+            TeamFeePayment {
+                token_program: ctx.accounts.token_program.to_account_info(),
+                from: ctx.accounts.program_payment_token_account.to_account_info(),
+                authority: ctx.accounts.pool_authority.to_account_info(),
+                signer_seeds: pool_seeds,
+                team_treasury: ctx.accounts.team_treasury.to_account_info(),
+                referral_pool: ctx.accounts.referral_pool.as_ref().map(|pool| pool.to_account_info()),
+                fee_split: &mut ctx.accounts.fee_split,
+                referral_account: ctx
+                    .accounts
+                    .referral_account
+                    .as_deref_mut()
+                    .map(|account| &mut **account),
+            },
+            lock_state.referral,
+            &ctx.accounts.protocol_state.referral_tiers,
+            team_fee,
+            0,
+        )?;
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.program_payment_token_account.to_account_info(),
+                    to: ctx.accounts.user_usdc_account.to_account_info(),
+                    authority: ctx.accounts.pool_authority.to_account_info(),
+                },
+                pool_seeds,
+            ),
+            user_receives,
+        )?;
+
+        let up_pool_bump = ctx.bumps.up_pool_authority;
+        let up_mint_key = ctx.accounts.up_usdc_mint.key();
+        let up_pool_signer_seeds: &[&[&[u8]]] =
+            &[&[b"token_account", up_mint_key.as_ref(), &[up_pool_bump]]];
+
+        token::burn(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Burn {
+                    mint: ctx.accounts.up_usdc_mint.to_account_info(),
+                    from: ctx.accounts.program_up_usdc_account.to_account_info(),
+                    authority: ctx.accounts.up_pool_authority.to_account_info(),
+                },
+                up_pool_signer_seeds,
+            ),
+            user_receives
+                .checked_add(team_fee)
+                .and_then(|v| v.checked_add(founder_fee))
+                .ok_or(CustomError::MathOverflow)?,
+        )?;
+
+        if let Some(position_mint) = lock_state.position_mint {
+            let receipt_mint = ctx
+                .accounts
+                .position_mint
+                .as_ref()
+                .ok_or(CustomError::MissingReceiptAccount)?;
+            let receipt_token_account = ctx
+                .accounts
+                .receipt_token_account
+                .as_ref()
+                .ok_or(CustomError::MissingReceiptAccount)?;
+            require!(
+                receipt_mint.key() == position_mint,
+                CustomError::InvalidReceipt
+            );
+
+            token::burn(
+                CpiContext::new(
+                    ctx.accounts.token_program.to_account_info(),
+                    Burn {
+                        mint: receipt_mint.to_account_info(),
+                        from: receipt_token_account.to_account_info(),
+                        authority: ctx.accounts.holder.to_account_info(),
+                    },
+                ),
+                1,
+            )?;
+            lock_state.position_mint = None;
+        }
+
+        lock_state.initialized = false;
+        lock_state.amount = 0;
+
+        Ok(())
+    }
+
+    /// Registers `new_founder`. Their share vests linearly over
+    /// `vesting_seconds` from now, with nothing claimable before
+    /// `cliff_seconds` have passed.
+    pub fn add_founder(
+        ctx: Context<AddFounder>,
+        new_founder: Pubkey,
+        cliff_seconds: u64,
+        vesting_seconds: u64,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.deployer.key() == ctx.accounts.metadata.deployer,
+            CustomError::Unauthorized
+        );
+
+        require!(
+            cliff_seconds <= vesting_seconds,
+            CustomError::InvalidVestingSchedule
+        );
+
+        let pool = &mut ctx.accounts.founders_pool;
+        let founder_state = &mut ctx.accounts.founder_state;
+        founder_state.founder = new_founder;
+        founder_state.shares = 1;
+        founder_state.claimed = 0;
+        founder_state.vesting_start = Clock::get()?.unix_timestamp;
+        founder_state.cliff_seconds = cliff_seconds;
+        founder_state.vesting_seconds = vesting_seconds;
+        pool.join(founder_state)?;
+
+        Ok(())
+    }
+
+    /// Registers a cohort of founders in one transaction. `remaining_accounts`
+    /// holds the writable `[b"founder", pubkey]` PDA of each spec, in order.
+    /// Fails as a whole if any founder is repeated or already registered.
+    pub fn add_founders<'info>(
+        ctx: Context<'_, '_, 'info, 'info, AddFounders<'info>>,
+        specs: Vec<FounderSpec>,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.deployer.key() == ctx.accounts.metadata.deployer,
+            CustomError::Unauthorized
+        );
+        require!(!specs.is_empty(), CustomError::InsufficientAmount);
+        require!(
+            ctx.remaining_accounts.len() == specs.len(),
+            CustomError::InvalidRemainingAccounts
+        );
+
+        let now = Clock::get()?.unix_timestamp;
+        let pool = &mut ctx.accounts.founders_pool;
+
+        for (i, (spec, founder_info)) in specs.iter().zip(ctx.remaining_accounts).enumerate() {
+            require!(
+                !specs[..i].iter().any(|other| other.founder == spec.founder),
+                CustomError::DuplicateFounder
+            );
+
+            let (expected_state, bump) =
+                Pubkey::find_program_address(&[b"founder", spec.founder.as_ref()], ctx.program_id);
+            require!(
+                founder_info.key() == expected_state,
+                CustomError::InvalidRemainingAccounts
+            );
+            require!(
+                founder_info.owner != ctx.program_id,
+                CustomError::DuplicateFounder
+            );
+
+            let cliff_seconds = spec.cliff_seconds.unwrap_or(0);
+            let vesting_seconds = spec.vesting_seconds.unwrap_or(cliff_seconds);
+            require!(
+                cliff_seconds <= vesting_seconds,
+                CustomError::InvalidVestingSchedule
+            );
+
+            create_founder_account(
+                &ctx.accounts.deployer.to_account_info(),
+                founder_info,
+                &ctx.accounts.system_program.to_account_info(),
+                &[b"founder", spec.founder.as_ref(), &[bump]],
+                ctx.program_id,
+            )?;
+
+            let mut founder_state = Founder {
+                founder: spec.founder,
+                shares: spec.shares.unwrap_or(1),
+                acc_per_share_start: 0,
+                claimed: 0,
+                vesting_start: now,
+                cliff_seconds,
+                vesting_seconds,
+            };
+            pool.join(&mut founder_state)?;
+
+            let mut data = founder_info.try_borrow_mut_data()?;
+            founder_state.try_serialize(&mut &mut data[..])?;
+        }
+
+        Ok(())
+    }
+
+    pub fn claim_founder_share(ctx: Context<ClaimFounderShare>) -> Result<()> {
+        let founder_state = &mut ctx.accounts.founder_state;
+        let claimable = founder_claimable(
+            &ctx.accounts.founders_pool,
+            founder_state,
+            Clock::get()?.unix_timestamp,
+        )?;
+
+        require!(claimable > 0, CustomError::NothingToClaim);
+
+        founder_state.claimed = founder_state
+            .claimed
+            .checked_add(claimable)
+            .ok_or(CustomError::MathOverflow)?;
+
+        require!(
+            ctx.accounts.founder_token_account.mint == ctx.accounts.founder_pool_token_account.mint,
+            CustomError::InvalidTokenMint
+        );
+
+        let bump = ctx.bumps.founder_authority;
+        let signer_seeds: &[&[&[u8]]] = &[&[b"founder_authority".as_ref(), &[bump]]];
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.founder_pool_token_account.to_account_info(),
+                    to: ctx.accounts.founder_token_account.to_account_info(),
+                    authority: ctx.accounts.founder_authority.to_account_info(),
+                },
+                signer_seeds,
+            ),
+            claimable,
+        )?;
+
+        Ok(())
+    }
+
+    /// Pushes vested founder revenue to founders without their signature.
+    /// `remaining_accounts` holds `(founder_state, founder_token_account)`
+    /// pairs, both writable. Entries that fail validation or have nothing to
+    /// claim are logged and skipped so one bad pair doesn't block the rest.
+    pub fn distribute_founder_shares<'info>(
+        ctx: Context<'_, '_, 'info, 'info, DistributeFounderShares<'info>>,
+    ) -> Result<()> {
+        let pairs = ctx.remaining_accounts.chunks_exact(2);
+        require!(
+            pairs.remainder().is_empty(),
+            CustomError::InvalidRemainingAccounts
+        );
+
+        let now = Clock::get()?.unix_timestamp;
+        let payment_mint = ctx.accounts.founder_pool_token_account.mint;
+        let bump = ctx.bumps.founder_authority;
+        let signer_seeds: &[&[&[u8]]] = &[&[b"founder_authority".as_ref(), &[bump]]];
+
+        for pair in pairs {
+            let (state_info, token_info) = (&pair[0], &pair[1]);
+            if !state_info.is_writable || !token_info.is_writable {
+                msg!("Skipping {}: accounts not writable", state_info.key);
+                continue;
+            }
+
+            let mut founder_state = match Account::<Founder>::try_from(state_info) {
+                Ok(founder_state) => founder_state,
+                Err(_) => {
+                    msg!("Skipping {}: not a founder account", state_info.key);
+                    continue;
+                }
+            };
+            let (expected_state, _) = Pubkey::find_program_address(
+                &[b"founder", founder_state.founder.as_ref()],
+                ctx.program_id,
+            );
+            if state_info.key() != expected_state {
+                msg!("Skipping {}: not a founder account", state_info.key);
+                continue;
+            }
+
+            let expected_token_account = anchor_spl::associated_token::get_associated_token_address(
+                &founder_state.founder,
+                &payment_mint,
+            );
+            if token_info.key() != expected_token_account
+                || Account::<TokenAccount>::try_from(token_info).is_err()
+            {
+                msg!("Skipping {}: invalid founder token account", founder_state.founder);
+                continue;
+            }
+
+            let claimable = match founder_claimable(&ctx.accounts.founders_pool, &founder_state, now) {
+                Ok(0) => continue,
+                Ok(claimable) => claimable,
+                Err(err) => {
+                    msg!("Skipping {}: {}", founder_state.founder, err);
+                    continue;
+                }
+            };
+
+            founder_state.claimed = match founder_state.claimed.checked_add(claimable) {
+                Some(claimed) => claimed,
+                None => {
+                    msg!("Skipping {}: claimed amount overflows", founder_state.founder);
+                    continue;
+                }
+            };
+            founder_state.exit(ctx.program_id)?;
+
+            token::transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: ctx.accounts.founder_pool_token_account.to_account_info(),
+                        to: token_info.clone(),
+                        authority: ctx.accounts.founder_authority.to_account_info(),
+                    },
+                    signer_seeds,
+                ),
+                claimable,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Moves the 60-slot `FoundersPool` layout onto per-founder PDAs.
+    /// `remaining_accounts` holds the writable `[b"founder", pubkey]` PDA of
+    /// each legacy founder, in slot order. Every legacy founder keeps one
+    /// share, its sixtieth of what was collected so far and what it has
+    /// already claimed; fees from then on are split by shares.
+    pub fn migrate_founders_pool<'info>(
+        ctx: Context<'_, '_, 'info, 'info, MigrateFoundersPool<'info>>,
+    ) -> Result<()> {
+        let pool_info = ctx.accounts.founders_pool.to_account_info();
+        let legacy = {
+            let data = pool_info.try_borrow_data()?;
+            require!(
+                data.len() >= 8 && data[..8] == FoundersPool::DISCRIMINATOR,
+                ErrorCode::AccountDiscriminatorMismatch
+            );
+            require!(data.len() > FoundersPool::LEN, CustomError::AlreadyMigrated);
+            LegacyFoundersPool::deserialize(&mut &data[8..])
+                .map_err(|_| ErrorCode::AccountDidNotDeserialize)?
+        };
+
+        let founder_count = legacy.founder_count as usize;
+        require!(
+            ctx.remaining_accounts.len() == founder_count,
+            CustomError::InvalidRemainingAccounts
+        );
+
+        let now = Clock::get()?.unix_timestamp;
+        let mut pool = FoundersPool {
+            total_collected: legacy.total_collected,
+            founder_count: 0,
+            total_shares: 0,
+            acc_per_share: 0,
+            undistributed: 0,
+        };
+
+        for (i, founder_info) in ctx.remaining_accounts.iter().enumerate() {
+            let founder = *legacy.founders.get(i).ok_or(CustomError::InvalidRemainingAccounts)?;
+            let claimed = *legacy
+                .claim_status
+                .get(i)
+                .ok_or(CustomError::InvalidRemainingAccounts)?;
+
+            let (expected_state, bump) =
+                Pubkey::find_program_address(&[b"founder", founder.as_ref()], ctx.program_id);
+            require!(
+                founder_info.key() == expected_state,
+                CustomError::InvalidRemainingAccounts
+            );
+            require!(
+                founder_info.owner != ctx.program_id,
+                CustomError::DuplicateFounder
+            );
+
+            create_founder_account(
+                &ctx.accounts.deployer.to_account_info(),
+                founder_info,
+                &ctx.accounts.system_program.to_account_info(),
+                &[b"founder", founder.as_ref(), &[bump]],
+                ctx.program_id,
+            )?;
+
+            let mut founder_state = Founder {
+                founder,
+                shares: 1,
+                acc_per_share_start: 0,
+                claimed,
+                vesting_start: now,
+                cliff_seconds: 0,
+                vesting_seconds: 0,
+            };
+            pool.join(&mut founder_state)?;
+
+            let mut data = founder_info.try_borrow_mut_data()?;
+            founder_state.try_serialize(&mut &mut data[..])?;
+        }
+        pool.acc_per_share = (legacy.total_collected as u128)
+            .checked_mul(ACC_PRECISION)
+            .ok_or(CustomError::MathOverflow)?
+            / LEGACY_FOUNDER_SLOTS as u128;
+
+        pool_info.realloc(FoundersPool::LEN, false)?;
+        {
+            let mut data = pool_info.try_borrow_mut_data()?;
+            pool.try_serialize(&mut &mut data[..])?;
+        }
+
+        let excess = pool_info
+            .lamports()
+            .saturating_sub(Rent::get()?.minimum_balance(FoundersPool::LEN));
+        let deployer_info = ctx.accounts.deployer.to_account_info();
+        **pool_info.try_borrow_mut_lamports()? -= excess;
+        **deployer_info.try_borrow_mut_lamports()? = deployer_info
+            .lamports()
+            .checked_add(excess)
+            .ok_or(CustomError::MathOverflow)?;
+
+        Ok(())
+    }
+
+    pub fn leverage_buy(
+        ctx: Context<LeverageBuy>,
+        position_id: u64,
+        amount: u64,
+        leverage_bps: u64,
+        lock_seconds: u64,
+        referral: Option<Pubkey>,
+        referral_code: Option<String>,
+    ) -> Result<()> {
+        let referral = resolve_referral(
+            referral,
+            referral_code.as_deref(),
+            ctx.accounts.referral_code_account.as_deref().map(|account| &**account),
+        )?;
+        let real_liquidity = ctx.accounts.program_payment_token_account.amount;
+        let max_leverage_bps =
+            effective_max_leverage_bps(&ctx.accounts.protocol_state, real_liquidity)?;
+        require!(
+            leverage_bps > 10_000 && leverage_bps <= max_leverage_bps,
+            CustomError::InvalidLeverageMultiplier
+        );
+        let clock = Clock::get()?;
+        let leverage_position = &mut ctx.accounts.leverage_position;
+        require!(
+            !leverage_position.initialized,
+            CustomError::AlreadyInitialized
+        );
+
+        let registry = &mut ctx.accounts.leverage_registry;
+        require!(
+            position_id == registry.next_id,
+            CustomError::InvalidPositionId
+        );
+        require!(
+            registry.open_ids.len() < MAX_LEVERAGE_POSITIONS,
+            CustomError::TooManyPositions
+        );
+        registry.user = ctx.accounts.user.key();
+        registry.next_id = registry
+            .next_id
+            .checked_add(1)
+            .ok_or(CustomError::MathOverflow)?;
+        registry.open_ids.push(position_id);
+
+        validate_lock_seconds(&ctx.accounts.protocol_state, lock_seconds)?;
+
+        require!(ctx.accounts.metadata.initialized, CustomError::AlreadyInitialized);
+
+        let payment_token_mint = ctx.accounts.metadata.payment_token;
+        let up_usdc_mint = ctx.accounts.metadata.up_usdc_mint;
+
+        let expected_program_payment_token_account = anchor_spl::associated_token::get_associated_token_address(
+            &ctx.accounts.pool_authority.key(),
+            &payment_token_mint,
+        );
+        require!(
+            ctx.accounts.program_payment_token_account.key() == expected_program_payment_token_account,
+            CustomError::InvalidProgramPaymentTokenAccount
+        );
+
+        let expected_founder_pool_token_account = anchor_spl::associated_token::get_associated_token_address(
+            &ctx.accounts.founder_authority.key(),
+            &payment_token_mint,
+        );
+        require!(
+            ctx.accounts.founder_pool_token_account.key() == expected_founder_pool_token_account,
+            CustomError::InvalidFounderPoolTokenAccount
+        );
+
+        let expected_program_up_usdc_account = anchor_spl::associated_token::get_associated_token_address(
+            &ctx.accounts.up_pool_authority.key(),
+            &up_usdc_mint,
+        );
+        require!(
+            ctx.accounts.program_up_usdc_account.key() == expected_program_up_usdc_account,
+            CustomError::InvalidProgramUpUsdcAccount
+        );
+
+
+        let borrow_bps = leverage_bps
+            .checked_sub(10_000)
+            .ok_or(CustomError::MathUnderflow)?;
+        let borrow_amount = bps_of(amount, borrow_bps)?;
+
+        let total_usdc = amount
+            .checked_add(borrow_amount)
+            .ok_or(CustomError::MathOverflow)?;
+
+        let protocol_state = &mut ctx.accounts.protocol_state;
+        let total_borrowed = protocol_state
+            .total_borrowed
+            .checked_add(borrow_amount)
+            .ok_or(CustomError::MathOverflow)?;
+        require!(
+            total_borrowed <= protocol_state.max_total_borrowed,
+            CustomError::BorrowCapExceeded
+        );
+        require!(
+            total_borrowed <= bps_of(real_liquidity, protocol_state.max_borrow_utilization_bps)?,
+            CustomError::BorrowCapExceeded
+        );
+        protocol_state.total_borrowed = total_borrowed;
+
+        let config =
+            get_lock_fee_config(lock_seconds, &ctx.accounts.protocol_state.fee_tiers)?;
+
+        let team_share = bps_of(total_usdc, config.team_bps)?;
+        let founder_fee = bps_of(total_usdc, config.founder_bps)?;
+        let locked_share = bps_of(total_usdc, config.liquidity_bps)?;
+        let usdc_for_tokens = total_usdc
+            .checked_sub(team_share)
+            .and_then(|v| v.checked_sub(founder_fee))
+            .and_then(|v| v.checked_sub(locked_share))
+            .ok_or(CustomError::MathUnderflow)?;
+        let user_amount_after_fees = usdc_for_tokens
+            .checked_sub(borrow_amount)
+            .ok_or(CustomError::MathUnderflow)?;
+        let liquidity_balance =
+            token::accessor::amount(&ctx.accounts.program_up_usdc_account.to_account_info())?;
+
+        let token_supply = ctx.accounts.token_mint.supply;
+
+        let mintable_tokens =
+            quote_mintable_tokens(liquidity_balance, token_supply, usdc_for_tokens, locked_share)?;
+
+        require!(mintable_tokens > 0, CustomError::InsufficientAmount);
+
+        pay_team_fee(
+            TeamFeePayment {
+                token_program: ctx.accounts.token_program.to_account_info(),
+                from: ctx.accounts.user_usdc_account.to_account_info(),
+                authority: ctx.accounts.user.to_account_info(),
+                signer_seeds: &[],
+                team_treasury: ctx.accounts.team_treasury.to_account_info(),
+                referral_pool: ctx.accounts.referral_pool.as_ref().map(|pool| pool.to_account_info()),
+                fee_split: &mut ctx.accounts.fee_split,
+                referral_account: ctx
+                    .accounts
+                    .referral_account
+                    .as_deref_mut()
+                    .map(|account| &mut **account),
+            },
+            referral,
+            &ctx.accounts.protocol_state.referral_tiers,
+            team_share,
+            total_usdc,
+        )?;
+
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                token::Transfer {
+                    from: ctx.accounts.user_usdc_account.to_account_info(),
+                    to: ctx.accounts.founder_pool_token_account.to_account_info(),
+                    authority: ctx.accounts.user.to_account_info(),
+                },
+            ),
+            founder_fee,
+        )?;
+        let pool = &mut ctx.accounts.founders_pool;
+        pool.credit(founder_fee)?;
+
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.user_usdc_account.to_account_info(),
+                    to: ctx.accounts.program_payment_token_account.to_account_info(),
+                    authority: ctx.accounts.user.to_account_info(),
+                },
+            ),
+            user_amount_after_fees
+                .checked_add(locked_share)
+                .ok_or(CustomError::MathOverflow)?,
+        )?;
+
+        let up_usdc_mint_bump = ctx.bumps.up_usdc_mint_authority;
+        let up_usdc_signer_seeds: &[&[&[u8]]] =
+            &[&[b"up_usdc_mint_authority", &[up_usdc_mint_bump]]];
+
+        token::mint_to(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                token::MintTo {
+                    mint: ctx.accounts.up_usdc_mint.to_account_info(),
+                    to: ctx.accounts.program_up_usdc_account.to_account_info(),
+                    authority: ctx.accounts.up_usdc_mint_authority.to_account_info(),
+                },
+                up_usdc_signer_seeds,
+            ),
+            usdc_for_tokens
+                .checked_add(locked_share)
+                .ok_or(CustomError::MathOverflow)?,
+        )?;
+
+        let mint_bump = ctx.bumps.mint_authority;
+        let signer_seeds: &[&[&[u8]]] = &[&[b"mint_authority", &[mint_bump]]];
+
+        token::mint_to(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                MintTo {
+                    mint: ctx.accounts.token_mint.to_account_info(),
+                    to: ctx.accounts.vault_token_account.to_account_info(),
+                    authority: ctx.accounts.mint_authority.to_account_info(),
+                },
+                signer_seeds,
+            ),
+            mintable_tokens,
+        )?;
+
+        leverage_position.user = ctx.accounts.user.key();
+        leverage_position.amount_user_paid = amount;
+        leverage_position.amount_borrowed = borrow_amount;
+        leverage_position.unlock_time = unlock_time_after(clock.unix_timestamp, lock_seconds)?;
+        leverage_position.referral = referral;
+        leverage_position.initialized = true;
+        leverage_position.lock_seconds = lock_seconds;
+        leverage_position.fee_config = config;
+        leverage_position.amount_minted = mintable_tokens;
+        leverage_position.leverage_bps = leverage_bps;
+        leverage_position.take_profit_price = None;
+        leverage_position.stop_loss_price = None;
+
+        Ok(())
+    }
+
+    pub fn leverage_sell(ctx: Context<LeverageSell>, position_id: u64) -> Result<()> {
+        let clock = Clock::get()?;
+        let position = &mut ctx.accounts.leverage_position;
+        require!(position.initialized, CustomError::AlreadyClaimed);
+        require!(
+            clock.unix_timestamp >= position.unlock_time,
+            CustomError::LockPeriodNotOver
+        );
+
+        require!(
+            ctx.accounts.program_payment_token_account.owner == ctx.accounts.pool_authority.key(),
+            CustomError::InvalidOwner
+        );
+
+        let amount_minted = position.amount_minted;
+        let liquidity_balance =
+            token::accessor::amount(&ctx.accounts.program_up_usdc_account.to_account_info())?;
+        let borrowed = position.amount_borrowed;
+
+        let config = position.fee_config.clone();
+
+        let quote = quote_exit(
+            liquidity_balance,
+            ctx.accounts.token_mint.supply,
+            amount_minted,
+            &config,
+        )?;
+        let settlement = settle_leverage(&quote, borrowed)?;
+        let founder_fee = settlement.founder_fee;
+        let team_fee = settlement.team_fee;
+        let user_cut = settlement.user_cut;
+
+        let vault_bump = ctx.bumps.vault_authority;
+        let position_id_bytes = position_id.to_le_bytes();
+        let vault_seeds: &[&[&[u8]]] = &[&[
+            b"l_vault",
+            ctx.accounts.user.key.as_ref(),
+            &position_id_bytes,
+            &[vault_bump],
+        ]];
+        token::burn(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Burn {
+                    mint: ctx.accounts.token_mint.to_account_info(),
+                    from: ctx.accounts.vault_token_account.to_account_info(),
+                    authority: ctx.accounts.vault_authority.to_account_info(),
+                },
+                vault_seeds,
+            ),
+            amount_minted,
+        )?;
+
+        let up_pool_bump = ctx.bumps.up_pool_authority;
+        let up_mint_key = ctx.accounts.up_usdc_mint.key();
+        let up_pool_signer_seeds: &[&[&[u8]]] =
+            &[&[b"token_account", up_mint_key.as_ref(), &[up_pool_bump]]];
+
+        token::burn(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                token::Burn {
+                    mint: ctx.accounts.up_usdc_mint.to_account_info(),
+                    from: ctx.accounts.program_up_usdc_account.to_account_info(),
+                    authority: ctx.accounts.up_pool_authority.to_account_info(),
+                },
+                up_pool_signer_seeds,
+            ),
+            user_cut
+                .checked_add(team_fee)
+                .and_then(|v| v.checked_add(founder_fee))
+                .and_then(|v| v.checked_add(settlement.repaid))
+                .ok_or(CustomError::MathOverflow)?,
+        )?;
+
+        let pool_bump = ctx.bumps.pool_authority;
+        let pool_seeds: &[&[&[u8]]] = &[&[
+            b"token_account",
+            ctx.accounts.metadata.payment_token.as_ref(),
+            &[pool_bump],
+        ]];
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.program_payment_token_account.to_account_info(),
+                    to: ctx.accounts.founder_pool_token_account.to_account_info(),
+                    authority: ctx.accounts.pool_authority.to_account_info(),
+                },
+                pool_seeds,
+            ),
+            founder_fee,
+        )?;
+
+        let pool = &mut ctx.accounts.founders_pool;
+        pool.credit(founder_fee)?;
+
+        pay_team_fee(
+            TeamFeePayment {
+                token_program: ctx.accounts.token_program.to_account_info(),
+                from: ctx.accounts.program_payment_token_account.to_account_info(),
+                authority: ctx.accounts.pool_authority.to_account_info(),
+                signer_seeds: pool_seeds,
+                team_treasury: ctx.accounts.team_treasury.to_account_info(),
+                referral_pool: ctx.accounts.referral_pool.as_ref().map(|pool| pool.to_account_info()),
+                fee_split: &mut ctx.accounts.fee_split,
+                referral_account: ctx
+                    .accounts
+                    .referral_account
+                    .as_deref_mut()
+                    .map(|account| &mut **account),
+            },
+            position.referral,
+            &ctx.accounts.protocol_state.referral_tiers,
+            team_fee,
+            0,
+        )?;
+
+        if user_cut > 0 {
+            token::transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: ctx.accounts.program_payment_token_account.to_account_info(),
+                        to: ctx.accounts.user_usdc_account.to_account_info(),
+                        authority: ctx.accounts.pool_authority.to_account_info(),
+                    },
+                    pool_seeds,
+                ),
+                user_cut,
+            )?;
+        }
+
+        let protocol_state = &mut ctx.accounts.protocol_state;
+        protocol_state.release_borrow(borrowed);
+        protocol_state.bad_debt = protocol_state
+            .bad_debt
+            .checked_add(settlement.bad_debt)
+            .ok_or(CustomError::MathOverflow)?;
+
+        ctx.accounts.leverage_registry.remove(position_id);
+
+        position.initialized = false;
+        position.amount_minted = 0;
+        position.amount_borrowed = 0;
+        position.amount_user_paid = 0;
+        position.take_profit_price = None;
+        position.stop_loss_price = None;
+
+        Ok(())
+    }
+
+    pub fn early_close_leverage(
+        ctx: Context<EarlyCloseLeverage>,
+        position_id: u64,
+    ) -> Result<()> {
+        let leverage_position = &mut ctx.accounts.leverage_position;
+        require!(leverage_position.initialized, CustomError::AlreadyClaimed);
+
+        require!(
+            ctx.accounts.program_payment_token_account.owner == ctx.accounts.pool_authority.key(),
+            CustomError::InvalidOwner
+        );
+
+        let expected_program_up_usdc_account = anchor_spl::associated_token::get_associated_token_address(
+            &ctx.accounts.up_pool_authority.key(),
+            &ctx.accounts.metadata.up_usdc_mint,
+        );
+        require!(
+            ctx.accounts.program_up_usdc_account.key() == expected_program_up_usdc_account,
+            CustomError::InvalidProgramUpUsdcAccount
+        );
+
+        let amount_minted = leverage_position.amount_minted;
+        let lock_seconds = leverage_position.lock_seconds;
+        let mut config = leverage_position.fee_config.clone();
+        apply_early_penalty(
+            &mut config,
+            &ctx.accounts.protocol_state,
+            leverage_position.unlock_time,
+            lock_seconds,
+            Clock::get()?.unix_timestamp,
+        )?;
+
+        let borrowed = leverage_position.amount_borrowed;
+
+        let liquidity_balance =
+            token::accessor::amount(&ctx.accounts.program_up_usdc_account.to_account_info())?;
+        let quote = quote_exit(
+            liquidity_balance,
+            ctx.accounts.token_mint.supply,
+            amount_minted,
+            &config,
+        )?;
+        let settlement = settle_leverage(&quote, borrowed)?;
+        let founder_fee = settlement.founder_fee;
+        let team_fee = settlement.team_fee;
+
+        let user_cut = settlement.user_cut;
+
+        let vault_bump = ctx.bumps.vault_authority;
+        let position_id_bytes = position_id.to_le_bytes();
+        let vault_seeds: &[&[&[u8]]] = &[&[
+            b"l_vault",
+            ctx.accounts.user.key.as_ref(),
+            &position_id_bytes,
+            &[vault_bump],
+        ]];
+        token::burn(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Burn {
+                    mint: ctx.accounts.token_mint.to_account_info(),
+                    from: ctx.accounts.vault_token_account.to_account_info(),
+                    authority: ctx.accounts.vault_authority.to_account_info(),
+                },
+                vault_seeds,
+            ),
+            amount_minted,
+        )?;
+
+        let up_pool_bump = ctx.bumps.up_pool_authority;
+        let up_mint_key = ctx.accounts.up_usdc_mint.key();
+        let up_pool_signer_seeds: &[&[&[u8]]] =
+            &[&[b"token_account", up_mint_key.as_ref(), &[up_pool_bump]]];
+
+        token::burn(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                token::Burn {
+                    mint: ctx.accounts.up_usdc_mint.to_account_info(),
+                    from: ctx.accounts.program_up_usdc_account.to_account_info(),
+                    authority: ctx.accounts.up_pool_authority.to_account_info(),
+                },
+                up_pool_signer_seeds,
+            ),
+            user_cut
+                .checked_add(team_fee)
+                .and_then(|v| v.checked_add(founder_fee))
+                .and_then(|v| v.checked_add(settlement.repaid))
+                .ok_or(CustomError::MathOverflow)?,
+        )?;
+
+        let pool_bump = ctx.bumps.pool_authority;
+        let pool_seeds: &[&[&[u8]]] = &[&[
+            b"token_account",
+            ctx.accounts.metadata.payment_token.as_ref(),
+            &[pool_bump],
+        ]];
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.program_payment_token_account.to_account_info(),
+                    to: ctx.accounts.founder_pool_token_account.to_account_info(),
+                    authority: ctx.accounts.pool_authority.to_account_info(),
+                },
+                pool_seeds,
+            ),
+            founder_fee,
+        )?;
+
+        let pool = &mut ctx.accounts.founders_pool;
+        pool.credit(founder_fee)?;
+
+        pay_team_fee(
+            TeamFeePayment {
+                token_program: ctx.accounts.token_program.to_account_info(),
+                from: ctx.accounts.program_payment_token_account.to_account_info(),
+                authority: ctx.accounts.pool_authority.to_account_info(),
+                signer_seeds: pool_seeds,
+                team_treasury: ctx.accounts.team_treasury.to_account_info(),
+                referral_pool: ctx.accounts.referral_pool.as_ref().map(|pool| pool.to_account_info()),
+                fee_split: &mut ctx.accounts.fee_split,
+                referral_account: ctx
+                    .accounts
+                    .referral_account
+                    .as_deref_mut()
+                    .map(|account| &mut **account),
+            },
+            leverage_position.referral,
+            &ctx.accounts.protocol_state.referral_tiers,
+            team_fee,
+            0,
+        )?;
+
+        if user_cut > 0 {
+            token::transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: ctx.accounts.program_payment_token_account.to_account_info(),
+                        to: ctx.accounts.user_usdc_account.to_account_info(),
+                        authority: ctx.accounts.pool_authority.to_account_info(),
+                    },
+                    pool_seeds,
+                ),
+                user_cut,
+            )?;
+        }
+
+        let protocol_state = &mut ctx.accounts.protocol_state;
+        protocol_state.release_borrow(borrowed);
+        protocol_state.bad_debt = protocol_state
+            .bad_debt
+            .checked_add(settlement.bad_debt)
+            .ok_or(CustomError::MathOverflow)?;
+
+        ctx.accounts.leverage_registry.remove(position_id);
+
+        leverage_position.initialized = false;
+        leverage_position.amount_minted = 0;
+        leverage_position.amount_borrowed = 0;
+        leverage_position.amount_user_paid = 0;
+        leverage_position.take_profit_price = None;
+        leverage_position.stop_loss_price = None;
+
+        Ok(())
+    }
+
+    /// Sets or clears the take-profit and stop-loss prices of the caller's
+    /// leverage position. Prices are in payment token base units per whole UP.
+    pub fn set_leverage_order(
+        ctx: Context<SetLeverageOrder>,
+        _position_id: u64,
+        take_profit_price: Option<u64>,
+        stop_loss_price: Option<u64>,
+    ) -> Result<()> {
+        let position = &mut ctx.accounts.leverage_position;
+        require!(position.initialized, CustomError::AlreadyClaimed);
+
+        if let (Some(take_profit), Some(stop_loss)) = (take_profit_price, stop_loss_price) {
+            require!(take_profit > stop_loss, CustomError::InvalidOrderPrices);
+        }
+
+        position.take_profit_price = take_profit_price;
+        position.stop_loss_price = stop_loss_price;
+        position.order_set_slot = Clock::get()?.slot;
+        position.observed_price = 0;
+        position.observed_slot = 0;
+
+        Ok(())
+    }
+
+    /// Records the current spot price against a leverage position. Its order
+    /// only executes when a price observed after the order was set, in an
+    /// earlier slot than the execution, also crosses the trigger, so the spot
+    /// price cannot be pushed through a trigger within the executing
+    /// transaction.
+    pub fn observe_price(ctx: Context<ObservePrice>, _position_id: u64) -> Result<()> {
+        let liquidity_balance =
+            token::accessor::amount(&ctx.accounts.program_up_usdc_account.to_account_info())?;
+        let position = &mut ctx.accounts.leverage_position;
+        require!(position.initialized, CustomError::AlreadyClaimed);
+        position.observed_price = spot_price(liquidity_balance, ctx.accounts.token_mint.supply)?;
+        position.observed_slot = Clock::get()?.slot;
+
+        Ok(())
+    }
+
+    /// Closes a leverage position whose take-profit or stop-loss price has been
+    /// crossed. Anyone may call it; the keeper tip is taken out of the team fee.
+    /// Before `unlock_time` the early exit penalty applies.
+    pub fn execute_leverage_order(
+        ctx: Context<ExecuteLeverageOrder>,
+        position_id: u64,
+    ) -> Result<()> {
+        let clock = Clock::get()?;
+        let leverage_position = &mut ctx.accounts.leverage_position;
+        require!(leverage_position.initialized, CustomError::AlreadyClaimed);
+
+        require!(
+            ctx.accounts.program_payment_token_account.owner == ctx.accounts.pool_authority.key(),
+            CustomError::InvalidOwner
+        );
+
+        let expected_program_up_usdc_account = anchor_spl::associated_token::get_associated_token_address(
+            &ctx.accounts.up_pool_authority.key(),
+            &ctx.accounts.metadata.up_usdc_mint,
+        );
+        require!(
+            ctx.accounts.program_up_usdc_account.key() == expected_program_up_usdc_account,
+            CustomError::InvalidProgramUpUsdcAccount
+        );
+
+        let amount_minted = leverage_position.amount_minted;
+        let lock_seconds = leverage_position.lock_seconds;
+        let liquidity_balance =
+            token::accessor::amount(&ctx.accounts.program_up_usdc_account.to_account_info())?;
+        let price = spot_price(liquidity_balance, ctx.accounts.token_mint.supply)?;
+        require!(
+            leverage_position.observed_slot > leverage_position.order_set_slot
+                && leverage_position.observed_slot < clock.slot
+                && clock.slot - leverage_position.observed_slot <= MAX_PRICE_AGE_SLOTS,
+            CustomError::StalePriceObservation
+        );
+        require!(
+            leverage_position.order_triggered(leverage_position.observed_price)
+                && leverage_position.order_triggered(price),
+            CustomError::OrderNotTriggered
+        );
+
+        let mut config = leverage_position.fee_config.clone();
+        if clock.unix_timestamp < leverage_position.unlock_time {
+            apply_early_penalty(
+                &mut config,
+                &ctx.accounts.protocol_state,
+                leverage_position.unlock_time,
+                lock_seconds,
+                clock.unix_timestamp,
+            )?;
+        }
+
+        let borrowed = leverage_position.amount_borrowed;
+
+        let quote = quote_exit(
+            liquidity_balance,
+            ctx.accounts.token_mint.supply,
+            amount_minted,
+            &config,
+        )?;
+        let settlement = settle_leverage(&quote, borrowed)?;
+        let founder_fee = settlement.founder_fee;
+        let keeper_tip = bps_of(settlement.team_fee, KEEPER_TIP_BPS)?;
+        let team_fee = settlement
+            .team_fee
+            .checked_sub(keeper_tip)
+            .ok_or(CustomError::MathUnderflow)?;
+
+        let user_cut = settlement.user_cut;
+
+        let vault_bump = ctx.bumps.vault_authority;
+        let position_id_bytes = position_id.to_le_bytes();
+        let vault_seeds: &[&[&[u8]]] = &[&[
+            b"l_vault",
+            ctx.accounts.user.key.as_ref(),
+            &position_id_bytes,
+            &[vault_bump],
+        ]];
+        token::burn(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Burn {
+                    mint: ctx.accounts.token_mint.to_account_info(),
+                    from: ctx.accounts.vault_token_account.to_account_info(),
+                    authority: ctx.accounts.vault_authority.to_account_info(),
+                },
+                vault_seeds,
+            ),
+            amount_minted,
+        )?;
+
+        let up_pool_bump = ctx.bumps.up_pool_authority;
+        let up_mint_key = ctx.accounts.up_usdc_mint.key();
+        let up_pool_signer_seeds: &[&[&[u8]]] =
+            &[&[b"token_account", up_mint_key.as_ref(), &[up_pool_bump]]];
+
+        token::burn(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                token::Burn {
+                    mint: ctx.accounts.up_usdc_mint.to_account_info(),
+                    from: ctx.accounts.program_up_usdc_account.to_account_info(),
+                    authority: ctx.accounts.up_pool_authority.to_account_info(),
+                },
+                up_pool_signer_seeds,
+            ),
+            user_cut
+                .checked_add(settlement.team_fee)
+                .and_then(|v| v.checked_add(founder_fee))
+                .and_then(|v| v.checked_add(settlement.repaid))
+                .ok_or(CustomError::MathOverflow)?,
+        )?;
+
+        let pool_bump = ctx.bumps.pool_authority;
+        let pool_seeds: &[&[&[u8]]] = &[&[
+            b"token_account",
+            ctx.accounts.metadata.payment_token.as_ref(),
+            &[pool_bump],
+        ]];
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.program_payment_token_account.to_account_info(),
+                    to: ctx.accounts.founder_pool_token_account.to_account_info(),
+                    authority: ctx.accounts.pool_authority.to_account_info(),
+                },
+                pool_seeds,
+            ),
+            founder_fee,
+        )?;
+
+        let pool = &mut ctx.accounts.founders_pool;
+        pool.credit(founder_fee)?;
+
+        pay_team_fee(
+            TeamFeePayment {
+                token_program: ctx.accounts.token_program.to_account_info(),
+                from: ctx.accounts.program_payment_token_account.to_account_info(),
+                authority: ctx.accounts.pool_authority.to_account_info(),
+                signer_seeds: pool_seeds,
+                team_treasury: ctx.accounts.team_treasury.to_account_info(),
+                referral_pool: ctx.accounts.referral_pool.as_ref().map(|pool| pool.to_account_info()),
+                fee_split: &mut ctx.accounts.fee_split,
+                referral_account: ctx
+                    .accounts
+                    .referral_account
+                    .as_deref_mut()
+                    .map(|account| &mut **account),
+            },
+            leverage_position.referral,
+            &ctx.accounts.protocol_state.referral_tiers,
+            team_fee,
+            0,
+        )?;
+
+        if keeper_tip > 0 {
+            token::transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: ctx.accounts.program_payment_token_account.to_account_info(),
+                        to: ctx.accounts.keeper_usdc_account.to_account_info(),
+                        authority: ctx.accounts.pool_authority.to_account_info(),
+                    },
+                    pool_seeds,
+                ),
+                keeper_tip,
+            )?;
+        }
+
+        if user_cut > 0 {
+            token::transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: ctx.accounts.program_payment_token_account.to_account_info(),
+                        to: ctx.accounts.user_usdc_account.to_account_info(),
+                        authority: ctx.accounts.pool_authority.to_account_info(),
+                    },
+                    pool_seeds,
+                ),
+                user_cut,
+            )?;
+        }
+
+        let protocol_state = &mut ctx.accounts.protocol_state;
+        protocol_state.release_borrow(borrowed);
+        protocol_state.bad_debt = protocol_state
+            .bad_debt
+            .checked_add(settlement.bad_debt)
+            .ok_or(CustomError::MathOverflow)?;
+
+        ctx.accounts.leverage_registry.remove(position_id);
+
+        leverage_position.initialized = false;
+        leverage_position.amount_minted = 0;
+        leverage_position.amount_borrowed = 0;
+        leverage_position.amount_user_paid = 0;
+        leverage_position.take_profit_price = None;
+        leverage_position.stop_loss_price = None;
+
+        Ok(())
+    }
+
+    /// Pays down `amount_borrowed` with fresh USDC from the user. upUSDC was
+    /// already minted for the borrowed amount when the position was opened, so
+    /// the deposit only backs it with real USDC.
+    pub fn add_collateral(
+        ctx: Context<AddCollateral>,
+        _position_id: u64,
+        amount: u64,
+    ) -> Result<()> {
+        let position = &mut ctx.accounts.leverage_position;
+        require!(position.initialized, CustomError::AlreadyClaimed);
+
+        let repaid = amount.min(position.amount_borrowed);
+        require!(repaid > 0, CustomError::InsufficientAmount);
+
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.user_usdc_account.to_account_info(),
+                    to: ctx.accounts.program_payment_token_account.to_account_info(),
+                    authority: ctx.accounts.user.to_account_info(),
+                },
+            ),
+            repaid,
+        )?;
+
+        position.amount_borrowed = position
+            .amount_borrowed
+            .checked_sub(repaid)
+            .ok_or(CustomError::MathUnderflow)?;
+        position.amount_user_paid = position
+            .amount_user_paid
+            .checked_add(repaid)
+            .ok_or(CustomError::MathOverflow)?;
+
+        ctx.accounts.protocol_state.release_borrow(repaid);
+
+        Ok(())
+    }
+
+    /// Sells `token_amount` of the position's UP and uses the proceeds to pay
+    /// down `amount_borrowed`; anything left after the debt is repaid goes to
+    /// the user. Before `unlock_time` the early exit penalty applies.
+    pub fn reduce_leverage(
+        ctx: Context<LeverageSell>,
+        position_id: u64,
+        token_amount: u64,
+    ) -> Result<()> {
+        let clock = Clock::get()?;
+        let position = &mut ctx.accounts.leverage_position;
+        require!(position.initialized, CustomError::AlreadyClaimed);
+        require!(
+            token_amount > 0 && token_amount < position.amount_minted,
+            CustomError::InsufficientAmount
+        );
+
+        require!(
+            ctx.accounts.program_payment_token_account.owner == ctx.accounts.pool_authority.key(),
+            CustomError::InvalidOwner
+        );
+
+        let liquidity_balance =
+            token::accessor::amount(&ctx.accounts.program_up_usdc_account.to_account_info())?;
+
+        let lock_seconds = position.lock_seconds;
+        let mut config = position.fee_config.clone();
+        if clock.unix_timestamp < position.unlock_time {
+            apply_early_penalty(
+                &mut config,
+                &ctx.accounts.protocol_state,
+                position.unlock_time,
+                lock_seconds,
+                clock.unix_timestamp,
+            )?;
+        }
+
+        let quote = quote_exit(
+            liquidity_balance,
+            ctx.accounts.token_mint.supply,
+            token_amount,
+            &config,
+        )?;
+        let founder_fee = quote.founder_fee;
+        let team_fee = quote.team_fee;
+        let proceeds = quote.after_fees()?;
+        let repaid = proceeds.min(position.amount_borrowed);
+        let user_cut = proceeds
+            .checked_sub(repaid)
+            .ok_or(CustomError::MathUnderflow)?;
+
+        let vault_bump = ctx.bumps.vault_authority;
+        let position_id_bytes = position_id.to_le_bytes();
+        let vault_seeds: &[&[&[u8]]] = &[&[
+            b"l_vault",
+            ctx.accounts.user.key.as_ref(),
+            &position_id_bytes,
+            &[vault_bump],
+        ]];
+        token::burn(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Burn {
+                    mint: ctx.accounts.token_mint.to_account_info(),
+                    from: ctx.accounts.vault_token_account.to_account_info(),
+                    authority: ctx.accounts.vault_authority.to_account_info(),
+                },
+                vault_seeds,
+            ),
+            token_amount,
+        )?;
+
+        let up_pool_bump = ctx.bumps.up_pool_authority;
+        let up_mint_key = ctx.accounts.up_usdc_mint.key();
+        let up_pool_signer_seeds: &[&[&[u8]]] =
+            &[&[b"token_account", up_mint_key.as_ref(), &[up_pool_bump]]];
+
+        token::burn(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                token::Burn {
+                    mint: ctx.accounts.up_usdc_mint.to_account_info(),
+                    from: ctx.accounts.program_up_usdc_account.to_account_info(),
+                    authority: ctx.accounts.up_pool_authority.to_account_info(),
+                },
+                up_pool_signer_seeds,
+            ),
+            user_cut
+                .checked_add(team_fee)
+                .and_then(|v| v.checked_add(founder_fee))
+                .and_then(|v| v.checked_add(repaid))
+                .ok_or(CustomError::MathOverflow)?,
+        )?;
+
+        let pool_bump = ctx.bumps.pool_authority;
+        let pool_seeds: &[&[&[u8]]] = &[&[
+            b"token_account",
+            ctx.accounts.metadata.payment_token.as_ref(),
+            &[pool_bump],
+        ]];
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.program_payment_token_account.to_account_info(),
+                    to: ctx.accounts.founder_pool_token_account.to_account_info(),
+                    authority: ctx.accounts.pool_authority.to_account_info(),
+                },
+                pool_seeds,
+            ),
+            founder_fee,
+        )?;
+
+        let pool = &mut ctx.accounts.founders_pool;
+        pool.credit(founder_fee)?;
+
+        pay_team_fee(
+            TeamFeePayment {
+                token_program: ctx.accounts.token_program.to_account_info(),
+                from: ctx.accounts.program_payment_token_account.to_account_info(),
+                authority: ctx.accounts.pool_authority.to_account_info(),
+                signer_seeds: pool_seeds,
+                team_treasury: ctx.accounts.team_treasury.to_account_info(),
+                referral_pool: ctx.accounts.referral_pool.as_ref().map(|pool| pool.to_account_info()),
+                fee_split: &mut ctx.accounts.fee_split,
+                referral_account: ctx
+                    .accounts
+                    .referral_account
+                    .as_deref_mut()
+                    .map(|account| &mut **account),
+            },
+            position.referral,
+            &ctx.accounts.protocol_state.referral_tiers,
+            team_fee,
+            0,
+        )?;
+
+        if user_cut > 0 {
+            token::transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: ctx.accounts.program_payment_token_account.to_account_info(),
+                        to: ctx.accounts.user_usdc_account.to_account_info(),
+                        authority: ctx.accounts.pool_authority.to_account_info(),
+                    },
+                    pool_seeds,
+                ),
+                user_cut,
+            )?;
+        }
+
+        ctx.accounts.protocol_state.release_borrow(repaid);
+
+        position.amount_minted = position
+            .amount_minted
+            .checked_sub(token_amount)
+            .ok_or(CustomError::MathUnderflow)?;
+        position.amount_borrowed = position
+            .amount_borrowed
+            .checked_sub(repaid)
+            .ok_or(CustomError::MathUnderflow)?;
+
+        Ok(())
+    }
+
+    pub fn close_lock_position(ctx: Context<CloseLockPosition>) -> Result<()> {
+        require!(
+            !ctx.accounts.lock_state.initialized,
+            CustomError::PositionStillOpen
+        );
+        require!(
+            ctx.accounts.vault_token_account.amount == 0,
+            CustomError::VaultNotEmpty
+        );
+
+        let vault_bump = ctx.bumps.vault_authority;
+        let vault_seeds: &[&[&[u8]]] =
+            &[&[b"vault", ctx.accounts.user.key.as_ref(), &[vault_bump]]];
+
+        token::close_account(CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            CloseAccount {
+                account: ctx.accounts.vault_token_account.to_account_info(),
+                destination: ctx.accounts.user.to_account_info(),
+                authority: ctx.accounts.vault_authority.to_account_info(),
+            },
+            vault_seeds,
+        ))?;
+
+        Ok(())
+    }
+
+    pub fn close_leverage_position(
+        ctx: Context<CloseLeveragePosition>,
+        position_id: u64,
+    ) -> Result<()> {
+        require!(
+            !ctx.accounts.leverage_position.initialized,
+            CustomError::PositionStillOpen
+        );
+        require!(
+            ctx.accounts.vault_token_account.amount == 0,
+            CustomError::VaultNotEmpty
+        );
+
+        let vault_bump = ctx.bumps.vault_authority;
+        let position_id_bytes = position_id.to_le_bytes();
+        let vault_seeds: &[&[&[u8]]] = &[&[
+            b"l_vault",
+            ctx.accounts.user.key.as_ref(),
+            &position_id_bytes,
+            &[vault_bump],
+        ]];
+
+        token::close_account(CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            CloseAccount {
+                account: ctx.accounts.vault_token_account.to_account_info(),
+                destination: ctx.accounts.user.to_account_info(),
+                authority: ctx.accounts.vault_authority.to_account_info(),
+            },
+            vault_seeds,
+        ))?;
+
+        Ok(())
+    }
+
+    /// Rewrites a lock created before `LockedTokenState` was versioned into
+    /// the current layout. Permissionless; the payer covers the extra rent.
+    pub fn migrate_lock_state(ctx: Context<MigrateLockState>) -> Result<()> {
+        let lock_info = ctx.accounts.lock_state.to_account_info();
+        require!(
+            lock_info.data_len() < LockedTokenState::LEN,
+            CustomError::AlreadyMigrated
+        );
+
+        let legacy = {
+            let data = lock_info.try_borrow_data()?;
+            require!(
+                data.len() >= 8 && data[..8] == LockedTokenState::DISCRIMINATOR,
+                ErrorCode::AccountDiscriminatorMismatch
+            );
+            LegacyLockedTokenState::deserialize(&mut &data[8..])
+                .map_err(|_| ErrorCode::AccountDidNotDeserialize)?
+        };
+
+        let required = Rent::get()?.minimum_balance(LockedTokenState::LEN);
+        let top_up = required.saturating_sub(lock_info.lamports());
+        if top_up > 0 {
+            anchor_lang::system_program::transfer(
+                CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    anchor_lang::system_program::Transfer {
+                        from: ctx.accounts.payer.to_account_info(),
+                        to: lock_info.clone(),
+                    },
+                ),
+                top_up,
+            )?;
+        }
+        lock_info.realloc(LockedTokenState::LEN, true)?;
+
+        let lock_seconds = legacy
+            .lock_hour
+            .checked_mul(3600)
+            .ok_or(CustomError::MathOverflow)?;
+        let start_time = legacy
+            .unlock_time
+            .checked_sub(i64::try_from(lock_seconds).map_err(|_| CustomError::MathOverflow)?)
+            .ok_or(CustomError::MathUnderflow)?;
+
+        let migrated = LockedTokenState {
+            user: legacy.user,
+            amount: legacy.amount,
+            unlock_time: legacy.unlock_time,
+            referral: legacy.referral,
+            initialized: legacy.initialized,
+            lock_seconds,
+            auto_settle: false,
+            auto_rollover: false,
+            position_mint: None,
+            vesting: false,
+            start_time,
+            end_time: legacy.unlock_time,
+            claimed_amount: 0,
+            version: LOCK_STATE_VERSION,
+            fee_config: get_lock_fee_config(lock_seconds, &default_fee_tiers())?,
+        };
+        let mut data = lock_info.try_borrow_mut_data()?;
+        migrated.try_serialize(&mut &mut data[..])?;
+
+        Ok(())
+    }
+
+    /// Moves an open position from the single-slot `[b"leverage", user]` and
+    /// `[b"l_vault", user]` accounts into the registry as `position_id`, then
+    /// closes the legacy accounts to the user.
+    pub fn migrate_legacy_leverage(
+        ctx: Context<MigrateLegacyLeverage>,
+        position_id: u64,
+    ) -> Result<()> {
+        let legacy_info = ctx.accounts.legacy_position.to_account_info();
+        let legacy = {
+            let data = legacy_info.try_borrow_data()?;
+            require!(
+                data.len() >= 8 && data[..8] == LeveragePosition::DISCRIMINATOR,
+                ErrorCode::AccountDiscriminatorMismatch
+            );
+            require!(data.len() < LeveragePosition::LEN, CustomError::AlreadyMigrated);
+            LegacyLeveragePosition::deserialize(&mut &data[8..])
+                .map_err(|_| ErrorCode::AccountDidNotDeserialize)?
+        };
+        require!(legacy.initialized, CustomError::AlreadyClaimed);
+        require!(legacy.amount_user_paid > 0, CustomError::InsufficientAmount);
+
+        let registry = &mut ctx.accounts.leverage_registry;
+        require!(
+            position_id == registry.next_id,
+            CustomError::InvalidPositionId
+        );
+        require!(
+            registry.open_ids.len() < MAX_LEVERAGE_POSITIONS,
+            CustomError::TooManyPositions
+        );
+        registry.user = ctx.accounts.user.key();
+        registry.next_id = registry
+            .next_id
+            .checked_add(1)
+            .ok_or(CustomError::MathOverflow)?;
+        registry.open_ids.push(position_id);
+
+        let legacy_vault_bump = ctx.bumps.legacy_vault_authority;
+        let legacy_vault_seeds: &[&[&[u8]]] =
+            &[&[b"l_vault", ctx.accounts.user.key.as_ref(), &[legacy_vault_bump]]];
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.legacy_vault_token_account.to_account_info(),
+                    to: ctx.accounts.vault_token_account.to_account_info(),
+                    authority: ctx.accounts.legacy_vault_authority.to_account_info(),
+                },
+                legacy_vault_seeds,
+            ),
+            ctx.accounts.legacy_vault_token_account.amount,
+        )?;
+        token::close_account(CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            CloseAccount {
+                account: ctx.accounts.legacy_vault_token_account.to_account_info(),
+                destination: ctx.accounts.user.to_account_info(),
+                authority: ctx.accounts.legacy_vault_authority.to_account_info(),
+            },
+            legacy_vault_seeds,
+        ))?;
+
+        let lock_seconds = legacy
+            .lock_hour
+            .checked_mul(3600)
+            .ok_or(CustomError::MathOverflow)?;
+        let leverage_bps = (legacy.amount_user_paid as u128)
+            .checked_add(legacy.amount_borrowed as u128)
+            .and_then(|v| v.checked_mul(10_000))
+            .and_then(|v| v.checked_div(legacy.amount_user_paid as u128))
+            .ok_or(CustomError::MathOverflow)?;
+
+        let position = &mut ctx.accounts.leverage_position;
+        position.user = legacy.user;
+        position.amount_user_paid = legacy.amount_user_paid;
+        position.amount_borrowed = legacy.amount_borrowed;
+        position.unlock_time = legacy.unlock_time;
+        position.referral = legacy.referral;
+        position.initialized = true;
+        position.lock_seconds = lock_seconds;
+        position.amount_minted = legacy.amount_minted;
+        position.leverage_bps =
+            u64::try_from(leverage_bps).map_err(|_| CustomError::MathOverflow)?;
+        position.take_profit_price = None;
+        position.stop_loss_price = None;
+        position.fee_config = get_lock_fee_config(lock_seconds, &default_fee_tiers())?;
+
+        let protocol_state = &mut ctx.accounts.protocol_state;
+        protocol_state.total_borrowed = protocol_state
+            .total_borrowed
+            .checked_add(legacy.amount_borrowed)
+            .ok_or(CustomError::MathOverflow)?;
+
+        close_raw_account(&legacy_info, &ctx.accounts.user.to_account_info())
+    }
+
+    /// Closes a legacy `[b"leverage", user]` position that was already closed
+    /// out before the registry existed, together with its empty
+    /// `[b"l_vault", user]` vault, refunding the rent to the user.
+    pub fn close_legacy_leverage(ctx: Context<CloseLegacyLeverage>) -> Result<()> {
+        let legacy_info = ctx.accounts.legacy_position.to_account_info();
+        let legacy = {
+            let data = legacy_info.try_borrow_data()?;
+            require!(
+                data.len() >= 8 && data[..8] == LeveragePosition::DISCRIMINATOR,
+                ErrorCode::AccountDiscriminatorMismatch
+            );
+            require!(data.len() < LeveragePosition::LEN, CustomError::AlreadyMigrated);
+            LegacyLeveragePosition::deserialize(&mut &data[8..])
+                .map_err(|_| ErrorCode::AccountDidNotDeserialize)?
+        };
+        require!(!legacy.initialized, CustomError::PositionStillOpen);
+        require!(
+            ctx.accounts.legacy_vault_token_account.amount == 0,
+            CustomError::VaultNotEmpty
+        );
+
+        let legacy_vault_bump = ctx.bumps.legacy_vault_authority;
+        let legacy_vault_seeds: &[&[&[u8]]] =
+            &[&[b"l_vault", ctx.accounts.user.key.as_ref(), &[legacy_vault_bump]]];
+        token::close_account(CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            CloseAccount {
+                account: ctx.accounts.legacy_vault_token_account.to_account_info(),
+                destination: ctx.accounts.user.to_account_info(),
+                authority: ctx.accounts.legacy_vault_authority.to_account_info(),
+            },
+            legacy_vault_seeds,
+        ))?;
+
+        close_raw_account(&legacy_info, &ctx.accounts.user.to_account_info())
+    }
+}
+
+/// Closes a program-owned account that isn't decoded through `Account`,
+/// moving its lamports to `destination`.
+fn close_raw_account<'info>(
+    account: &AccountInfo<'info>,
+    destination: &AccountInfo<'info>,
+) -> Result<()> {
+    let refund = account.lamports();
+    **destination.try_borrow_mut_lamports()? = destination
+        .lamports()
+        .checked_add(refund)
+        .ok_or(CustomError::MathOverflow)?;
+    **account.try_borrow_mut_lamports()? = 0;
+    account.assign(&anchor_lang::system_program::ID);
+    account.realloc(0, false)?;
+    Ok(())
+}
+
+/// Fee schedule for a lock of `lock_seconds`, interpolated linearly between
+/// the two surrounding tiers and clamped to the first and last tier.
+pub fn get_lock_fee_config(lock_seconds: u64, tiers: &[FeeTier]) -> Result<LockFeeConfig> {
+    let first = tiers.first().ok_or(CustomError::InvalidFeeTiers)?;
+    if lock_seconds <= first.lock_seconds {
+        return Ok(first.fee_config());
+    }
+
+    for pair in tiers.windows(2) {
+        let (lower, upper) = (&pair[0], &pair[1]);
+        if lock_seconds <= upper.lock_seconds {
+            let span = upper
+                .lock_seconds
+                .checked_sub(lower.lock_seconds)
+                .ok_or(CustomError::MathUnderflow)?;
+            let offset = lock_seconds
+                .checked_sub(lower.lock_seconds)
+                .ok_or(CustomError::MathUnderflow)?;
+            return Ok(LockFeeConfig {
+                liquidity_bps: interpolate_bps(
+                    lower.liquidity_bps,
+                    upper.liquidity_bps,
+                    offset,
+                    span,
+                )?,
+                team_bps: interpolate_bps(lower.team_bps, upper.team_bps, offset, span)?,
+                founder_bps: interpolate_bps(lower.founder_bps, upper.founder_bps, offset, span)?,
+            });
+        }
+    }
+
+    let last = tiers.last().ok_or(CustomError::InvalidFeeTiers)?;
+    Ok(last.fee_config())
+}
+
+pub fn interpolate_bps(from: u64, to: u64, offset: u64, span: u64) -> Result<u64> {
+    if span == 0 {
+        return Ok(to);
+    }
+
+    let delta = (from.abs_diff(to) as u128)
+        .checked_mul(offset as u128)
+        .ok_or(CustomError::MathOverflow)?
+        .checked_div(span as u128)
+        .ok_or(CustomError::MathOverflow)?;
+    let delta = u64::try_from(delta).map_err(|_| CustomError::MathOverflow)?;
+
+    if to >= from {
+        from.checked_add(delta).ok_or(CustomError::MathOverflow.into())
+    } else {
+        from.checked_sub(delta).ok_or(CustomError::MathUnderflow.into())
+    }
+}
+
+pub fn default_fee_tiers() -> Vec<FeeTier> {
+    [
+        (1, 150, 75),
+        (2, 225, 100),
+        (3, 300, 125),
+        (4, 375, 150),
+        (6, 450, 175),
+        (8, 550, 200),
+        (12, 725, 250),
+        (24, 925, 300),
+    ]
+    .iter()
+    .map(|&(hours, liquidity_bps, team_bps)| FeeTier {
+        lock_seconds: hours * 3600,
+        liquidity_bps,
+        team_bps,
+        founder_bps: 25,
+    })
+    .collect()
+}
+
+pub fn validate_lock_seconds(state: &ProtocolState, lock_seconds: u64) -> Result<()> {
+    require!(
+        lock_seconds >= state.min_lock_seconds && lock_seconds <= state.max_lock_seconds,
+        CustomError::InvalidLockPeriod
+    );
+    Ok(())
+}
+
+pub fn rollover_fee_config(config: &LockFeeConfig) -> LockFeeConfig {
+    LockFeeConfig {
+        liquidity_bps: config.liquidity_bps / ROLLOVER_FEE_DIVISOR,
+        team_bps: config.team_bps / ROLLOVER_FEE_DIVISOR,
+        founder_bps: config.founder_bps / ROLLOVER_FEE_DIVISOR,
+    }
+}
+
+pub fn authorize_lock_holder(
+    lock_state: &LockedTokenState,
+    owner: &Pubkey,
+    holder: &Pubkey,
+    receipt_token_account: Option<&Account<TokenAccount>>,
+) -> Result<()> {
+    match lock_state.position_mint {
+        Some(position_mint) => {
+            let receipt =
+                receipt_token_account.ok_or(CustomError::MissingReceiptAccount)?;
+            require!(receipt.mint == position_mint, CustomError::InvalidReceipt);
+            require!(receipt.owner == *holder, CustomError::InvalidReceipt);
+            require!(receipt.amount == 1, CustomError::InvalidReceipt);
+        }
+        None => require!(holder == owner, CustomError::Unauthorized),
+    }
+    Ok(())
+}
+
+pub fn vested_amount(total: u64, start_time: i64, end_time: i64, now: i64) -> Result<u64> {
+    if now >= end_time || end_time <= start_time {
+        return Ok(total);
+    }
+    if now <= start_time {
+        return Ok(0);
+    }
+
+    let elapsed = now
+        .checked_sub(start_time)
+        .ok_or(CustomError::MathUnderflow)?;
+    let duration = end_time
+        .checked_sub(start_time)
+        .ok_or(CustomError::MathUnderflow)?;
+    let vested = (total as u128)
+        .checked_mul(elapsed as u128)
+        .ok_or(CustomError::MathOverflow)?
+        .checked_div(duration as u128)
+        .ok_or(CustomError::MathOverflow)?;
+    u64::try_from(vested).map_err(|_| CustomError::MathOverflow.into())
+}
+
+/// Creates the `[b"founder", pubkey]` PDA signed by `signer_seeds` the way
+/// Anchor's `init` does: topping up rent, then allocating and assigning, so
+/// an address that was already funded can still be created.
+pub fn create_founder_account<'info>(
+    payer: &AccountInfo<'info>,
+    founder_info: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    signer_seeds: &[&[u8]],
+    program_id: &Pubkey,
+) -> Result<()> {
+    let top_up = Rent::get()?
+        .minimum_balance(Founder::LEN)
+        .saturating_sub(founder_info.lamports());
+    if top_up > 0 {
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                system_program.clone(),
+                anchor_lang::system_program::Transfer {
+                    from: payer.clone(),
+                    to: founder_info.clone(),
+                },
+            ),
+            top_up,
+        )?;
+    }
+
+    anchor_lang::system_program::allocate(
+        CpiContext::new_with_signer(
+            system_program.clone(),
+            anchor_lang::system_program::Allocate {
+                account_to_allocate: founder_info.clone(),
+            },
+            &[signer_seeds],
+        ),
+        Founder::LEN as u64,
+    )?;
+    anchor_lang::system_program::assign(
+        CpiContext::new_with_signer(
+            system_program.clone(),
+            anchor_lang::system_program::Assign {
+                account_to_assign: founder_info.clone(),
+            },
+            &[signer_seeds],
+        ),
+        program_id,
+    )?;
+
+    Ok(())
+}
+
+/// Vested and not yet claimed founder revenue for `founder_state`.
+pub fn founder_claimable(pool: &FoundersPool, founder_state: &Founder, now: i64) -> Result<u64> {
+    let entitlement = pool.entitlement(founder_state)?;
+    let vested = founder_vested_amount(
+        entitlement,
+        founder_state.vesting_start,
+        founder_state.cliff_seconds,
+        founder_state.vesting_seconds,
+        now,
+    )?;
+    Ok(vested.saturating_sub(founder_state.claimed))
+}
+
+/// A fee split lists at most `MAX_FEE_RECIPIENTS` distinct recipients whose
+/// shares add up to exactly 10_000 bps, or no recipients at all.
+pub fn validate_fee_split(recipients: &[FeeRecipientSpec]) -> Result<()> {
+    require!(
+        recipients.len() <= MAX_FEE_RECIPIENTS,
+        CustomError::InvalidFeeSplit
+    );
+    if recipients.is_empty() {
+        return Ok(());
+    }
+
+    let mut total_bps: u64 = 0;
+    for (i, spec) in recipients.iter().enumerate() {
+        require!(
+            !recipients[..i].iter().any(|other| other.recipient == spec.recipient),
+            CustomError::InvalidFeeSplit
+        );
+        total_bps = total_bps
+            .checked_add(spec.bps)
+            .ok_or(CustomError::MathOverflow)?;
+    }
+    require!(total_bps == 10_000, CustomError::InvalidFeeSplit);
+
+    Ok(())
+}
+
+/// Codes are 3 to `MAX_REFERRAL_CODE_LEN` characters of `a-z`, `0-9`, `_`
+/// and `-`, so each code has a single PDA.
+pub fn validate_referral_code(code: &str) -> Result<()> {
+    require!(
+        (MIN_REFERRAL_CODE_LEN..=MAX_REFERRAL_CODE_LEN).contains(&code.len()),
+        CustomError::InvalidReferralCode
+    );
+    require!(
+        code.bytes()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == b'_' || c == b'-'),
+        CustomError::InvalidReferralCode
+    );
+
+    Ok(())
+}
+
+/// Referrer named by `referral` or by `code`. When both are given they must
+/// agree.
+pub fn resolve_referral(
+    referral: Option<Pubkey>,
+    code: Option<&str>,
+    code_account: Option<&ReferralCode>,
+) -> Result<Option<Pubkey>> {
+    let Some(code) = code else {
+        return Ok(referral);
+    };
+    let code_account = code_account.ok_or(CustomError::MissingReferralAccount)?;
+    require!(code_account.code == code, CustomError::InvalidReferralCode);
+    if let Some(referrer) = referral {
+        require!(
+            referrer == code_account.referrer,
+            CustomError::InvalidReferral
+        );
+    }
+
+    Ok(Some(code_account.referrer))
+}
+
+/// Referrer's cut of `team_fee`, accrued to their `ReferralAccount`. The rate
+/// is the highest tier the referrer's volume so far qualifies for. Zero when
+/// the position has no referrer; a position with one must supply its
+/// `ReferralAccount`. Only entries pass a nonzero `volume`, so a position
+/// counts towards the tiers once.
+pub fn accrue_referral(
+    referral: Option<Pubkey>,
+    referral_account: Option<&mut ReferralAccount>,
+    tiers: &[ReferralTier],
+    team_fee: u64,
+    volume: u64,
+) -> Result<u64> {
+    let Some(referrer) = referral else {
+        return Ok(0);
+    };
+    let referral_account = referral_account.ok_or(CustomError::MissingReferralAccount)?;
+    require!(
+        referral_account.referrer == referrer,
+        CustomError::InvalidReferral
+    );
+
+    let share_bps = referral_share_bps(referral_account.referred_volume, tiers);
+    let share = bps_of(team_fee, share_bps)?;
+    referral_account.accrued = referral_account
+        .accrued
+        .checked_add(share)
+        .ok_or(CustomError::MathOverflow)?;
+    referral_account.total_earned = referral_account
+        .total_earned
+        .checked_add(share)
+        .ok_or(CustomError::MathOverflow)?;
+    referral_account.referred_volume = referral_account
+        .referred_volume
+        .checked_add(volume)
+        .ok_or(CustomError::MathOverflow)?;
+
+    Ok(share)
+}
+
+/// Share rate of the highest tier `referred_volume` qualifies for. Tiers are
+/// sorted by `min_volume`.
+pub fn referral_share_bps(referred_volume: u64, tiers: &[ReferralTier]) -> u64 {
+    tiers
+        .iter()
+        .filter(|tier| referred_volume >= tier.min_volume)
+        .map(|tier| tier.share_bps)
+        .next_back()
+        .unwrap_or(0)
+}
+
+/// Source and destinations of a team fee. `signer_seeds` is empty when
+/// `authority` signs the transaction itself.
+pub struct TeamFeePayment<'a, 'info> {
+    pub token_program: AccountInfo<'info>,
+    pub from: AccountInfo<'info>,
+    pub authority: AccountInfo<'info>,
+    pub signer_seeds: &'a [&'a [&'a [u8]]],
+    pub team_treasury: AccountInfo<'info>,
+    pub referral_pool: Option<AccountInfo<'info>>,
+    pub fee_split: &'a mut FeeSplit,
+    pub referral_account: Option<&'a mut ReferralAccount>,
+}
+
+/// Pays `team_fee` out of `payment.from`. The referral share goes to the
+/// referral pool, the rest to the team treasury where it is credited to the
+/// fee split. A position with a referrer must supply both its
+/// `ReferralAccount` and the referral pool.
+pub fn pay_team_fee(
+    payment: TeamFeePayment,
+    referral: Option<Pubkey>,
+    tiers: &[ReferralTier],
+    team_fee: u64,
+    volume: u64,
+) -> Result<()> {
+    let TeamFeePayment {
+        token_program,
+        from,
+        authority,
+        signer_seeds,
+        team_treasury,
+        referral_pool,
+        fee_split,
+        referral_account,
+    } = payment;
+
+    require!(
+        referral.is_none() || referral_pool.is_some(),
+        CustomError::MissingReferralAccount
+    );
+    let referral_share = accrue_referral(referral, referral_account, tiers, team_fee, volume)?;
+    let team_share = team_fee
+        .checked_sub(referral_share)
+        .ok_or(CustomError::MathUnderflow)?;
+
+    if let Some(referral_pool) = referral_pool.filter(|_| referral_share > 0) {
+        token::transfer(
+            CpiContext::new_with_signer(
+                token_program.clone(),
+                Transfer {
+                    from: from.clone(),
+                    to: referral_pool,
+                    authority: authority.clone(),
+                },
+                signer_seeds,
+            ),
+            referral_share,
+        )?;
+    }
+
+    token::transfer(
+        CpiContext::new_with_signer(
+            token_program,
+            Transfer {
+                from,
+                to: team_treasury,
+                authority,
+            },
+            signer_seeds,
+        ),
+        team_share,
+    )?;
+    fee_split.credit(team_share)?;
+
+    Ok(())
+}
+
+/// Portion of a founder's `entitlement` released by `now`: nothing before the
+/// cliff, then linear from `start_time` over `vesting_seconds`.
+pub fn founder_vested_amount(
+    entitlement: u64,
+    start_time: i64,
+    cliff_seconds: u64,
+    vesting_seconds: u64,
+    now: i64,
+) -> Result<u64> {
+    let cliff_end = unlock_time_after(start_time, cliff_seconds)?;
+    if now < cliff_end {
+        return Ok(0);
+    }
+    let end_time = unlock_time_after(start_time, vesting_seconds)?;
+    vested_amount(entitlement, start_time, end_time, now)
+}
+
+/// Adds the early exit penalty to `config`. The penalty scales linearly from
+/// `early_penalty_max_bps` at the start of the lock down to
+/// `early_penalty_min_bps` just before `unlock_time`.
+pub fn apply_early_penalty(
+    config: &mut LockFeeConfig,
+    state: &ProtocolState,
+    unlock_time: i64,
+    lock_seconds: u64,
+    now: i64,
+) -> Result<()> {
+    let lock_seconds = lock_seconds.max(1);
+    let remaining = u64::try_from(unlock_time.saturating_sub(now).max(0))
+        .map_err(|_| CustomError::MathOverflow)?
+        .min(lock_seconds);
+    let spread = state
+        .early_penalty_max_bps
+        .checked_sub(state.early_penalty_min_bps)
+        .ok_or(CustomError::MathUnderflow)?;
+    let scaled = (spread as u128)
+        .checked_mul(remaining as u128)
+        .ok_or(CustomError::MathOverflow)?
+        .checked_div(lock_seconds as u128)
+        .ok_or(CustomError::MathOverflow)?;
+    let penalty_bps = state
+        .early_penalty_min_bps
+        .checked_add(u64::try_from(scaled).map_err(|_| CustomError::MathOverflow)?)
+        .ok_or(CustomError::MathOverflow)?;
+    let headroom = MAX_EXIT_FEE_BPS.saturating_sub(
+        config
+            .liquidity_bps
+            .saturating_add(config.team_bps)
+            .saturating_add(config.founder_bps),
+    );
+    let penalty_bps = penalty_bps.min(headroom);
+
+    if state.early_penalty_to_liquidity {
+        config.liquidity_bps = config
+            .liquidity_bps
+            .checked_add(penalty_bps)
+            .ok_or(CustomError::MathOverflow)?;
+    } else {
+        config.team_bps = config
+            .team_bps
+            .checked_add(penalty_bps)
+            .ok_or(CustomError::MathOverflow)?;
+    }
+    Ok(())
+}
+
+/// Maximum leverage currently allowed. Starts at `max_leverage_bps` with no
+/// outstanding borrows and steps down linearly towards 1x as utilization of
+/// real USDC liquidity approaches `max_borrow_utilization_bps`.
+pub fn effective_max_leverage_bps(state: &ProtocolState, real_liquidity: u64) -> Result<u64> {
+    let cap_bps = state.max_borrow_utilization_bps as u128;
+    let utilization_bps = (state.total_borrowed as u128)
+        .checked_mul(10_000)
+        .ok_or(CustomError::MathOverflow)?
+        .checked_div(real_liquidity.max(1) as u128)
+        .ok_or(CustomError::MathOverflow)?;
+    if utilization_bps >= cap_bps {
+        return Ok(10_000);
+    }
+
+    let headroom_bps = state
+        .max_leverage_bps
+        .checked_sub(10_000)
+        .ok_or(CustomError::MathUnderflow)? as u128;
+    let allowed = headroom_bps
+        .checked_mul(cap_bps - utilization_bps)
+        .ok_or(CustomError::MathOverflow)?
+        .checked_div(cap_bps)
+        .ok_or(CustomError::MathOverflow)?;
+    let allowed = u64::try_from(allowed).map_err(|_| CustomError::MathOverflow)?;
+    10_000u64
+        .checked_add(allowed)
+        .ok_or(CustomError::MathOverflow.into())
+}
+
+pub fn bps_of(amount: u64, bps: u64) -> Result<u64> {
+    let value = (amount as u128)
+        .checked_mul(bps as u128)
+        .ok_or(CustomError::MathOverflow)?
+        .checked_div(10_000)
+        .ok_or(CustomError::MathOverflow)?;
+    u64::try_from(value).map_err(|_| CustomError::MathOverflow.into())
+}
+
+pub fn unlock_time_after(now: i64, lock_seconds: u64) -> Result<i64> {
+    let lock_seconds = i64::try_from(lock_seconds).map_err(|_| CustomError::MathOverflow)?;
+    now.checked_add(lock_seconds)
+        .ok_or(CustomError::MathOverflow.into())
+}
+
+pub fn quote_mintable_tokens(
+    liquidity_balance: u64,
+    token_supply: u64,
+    usdc_for_tokens: u64,
+    locked_share: u64,
+) -> Result<u64> {
+    let scale = 1_000_000_000u128;
+
+    let mut price_start = (liquidity_balance as u128)
+        .checked_mul(scale)
+        .ok_or(CustomError::MathOverflow)?
+        .checked_div(token_supply.max(1) as u128)
+        .ok_or(CustomError::MathOverflow)?;
+    if price_start == 0 {
+        price_start = 1;
+    }
+    let estimated_tokens = (usdc_for_tokens as u128)
+        .checked_mul(scale)
+        .ok_or(CustomError::MathOverflow)?
+        .checked_div(price_start)
+        .ok_or(CustomError::MathOverflow)?;
+    let liquidity_growth = (liquidity_balance as u128)
+        .checked_add(usdc_for_tokens as u128)
+        .and_then(|v| v.checked_add(locked_share as u128))
+        .ok_or(CustomError::MathOverflow)?;
+    let supply_after = (token_supply as u128)
+        .checked_add(estimated_tokens)
+        .ok_or(CustomError::MathOverflow)?;
+    let price_end = liquidity_growth
+        .checked_mul(scale)
+        .ok_or(CustomError::MathOverflow)?
+        .checked_div(supply_after.max(1))
+        .ok_or(CustomError::MathOverflow)?;
+    let avg_price = price_start
+        .checked_add(price_end)
+        .ok_or(CustomError::MathOverflow)?
+        / 2;
+    require!(avg_price > 0, CustomError::InsufficientAmount);
+
+    let mintable_tokens = (usdc_for_tokens as u128)
+        .checked_mul(scale)
+        .ok_or(CustomError::MathOverflow)?
+        .checked_div(avg_price)
+        .ok_or(CustomError::MathOverflow)?;
+    u64::try_from(mintable_tokens).map_err(|_| CustomError::MathOverflow.into())
+}
+
+/// UP price in payment token base units per whole token.
+pub fn spot_price(liquidity_balance: u64, token_supply: u64) -> Result<u64> {
+    let price = (liquidity_balance as u128)
+        .checked_mul(1_000_000_000)
+        .ok_or(CustomError::MathOverflow)?
+        / token_supply.max(1) as u128;
+    u64::try_from(price).map_err(|_| CustomError::MathOverflow.into())
+}
+
+/// How a closing leverage position's exit value is split.
+pub struct LeverageSettlement {
+    pub founder_fee: u64,
+    pub team_fee: u64,
+    pub user_cut: u64,
+    /// Borrowed upUSDC burned against the position's value.
+    pub repaid: u64,
+    /// Borrowed upUSDC left without backing.
+    pub bad_debt: u64,
+}
+
+/// Splits an exit quote between fees, the user and the loan. When the value
+/// after fees does not cover `borrowed`, fees are waived, the user receives
+/// nothing and whatever the full value cannot repay is reported as bad debt.
+pub fn settle_leverage(quote: &ExitQuote, borrowed: u64) -> Result<LeverageSettlement> {
+    let proceeds = quote.after_fees()?;
+    if proceeds >= borrowed {
+        return Ok(LeverageSettlement {
+            founder_fee: quote.founder_fee,
+            team_fee: quote.team_fee,
+            user_cut: proceeds - borrowed,
+            repaid: borrowed,
+            bad_debt: 0,
+        });
+    }
+
+    let repaid = quote.total_value.min(borrowed);
+    Ok(LeverageSettlement {
+        founder_fee: 0,
+        team_fee: 0,
+        user_cut: 0,
+        repaid,
+        bad_debt: borrowed - repaid,
+    })
+}
+
+pub struct ExitQuote {
+    pub total_value: u64,
+    pub founder_fee: u64,
+    pub team_fee: u64,
+    pub liquidity_fee: u64,
+}
+
+impl ExitQuote {
+    pub fn after_fees(&self) -> Result<u64> {
+        self.total_value
+            .checked_sub(self.founder_fee)
+            .and_then(|v| v.checked_sub(self.team_fee))
+            .and_then(|v| v.checked_sub(self.liquidity_fee))
+            .ok_or(CustomError::MathUnderflow.into())
+    }
+}
+
+pub fn quote_exit(
+    liquidity_balance: u64,
+    token_supply: u64,
+    token_amount: u64,
+    config: &LockFeeConfig,
+) -> Result<ExitQuote> {
+    let scale = 1_000_000_000u128;
+
+    let price = (liquidity_balance as u128)
+        .checked_mul(scale)
+        .ok_or(CustomError::MathOverflow)?
+        .checked_div(token_supply.max(1) as u128)
+        .ok_or(CustomError::MathOverflow)?;
+    let total_value = (token_amount as u128)
+        .checked_mul(price)
+        .ok_or(CustomError::MathOverflow)?
+        .checked_div(scale)
+        .ok_or(CustomError::MathOverflow)?;
+    let total_value = u64::try_from(total_value).map_err(|_| CustomError::MathOverflow)?;
+
+    let total_fee_bps = config
+        .liquidity_bps
+        .checked_add(config.team_bps)
+        .and_then(|v| v.checked_add(config.founder_bps))
+        .ok_or(CustomError::MathOverflow)?;
+    require!(total_fee_bps <= 10_000, CustomError::InvalidFeeTiers);
+
+    // The liquidity fee takes the rounding remainder, so the fees never sum
+    // to more than `bps_of(total_value, total_fee_bps)`.
+    let total_fees = bps_of(total_value, total_fee_bps)?;
+    let founder_fee = bps_of(total_value, config.founder_bps)?;
+    let team_fee = bps_of(total_value, config.team_bps)?;
+    let liquidity_fee = total_fees
+        .checked_sub(founder_fee)
+        .and_then(|v| v.checked_sub(team_fee))
+        .ok_or(CustomError::MathUnderflow)?;
+
+    Ok(ExitQuote {
+        total_value,
+        founder_fee,
+        team_fee,
+        liquidity_fee,
+    })
+}
+
+#[derive(Accounts)]
+pub struct Initialize<'info> {
+    #[account(mut)]
+    pub up_only_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + 4 + 6 + 4 + 2 + 160 + 32 + 1,
+        seeds = [b"metadata", up_only_mint.key().as_ref()],
+        bump
+    )]
+    pub metadata: Account<'info, TokenMetadata>,
+
+    #[account(
+        mut,
+        constraint = user_up_only_account.mint == up_only_mint.key()
+    )]
+    pub user_up_only_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = program_up_only_account.mint == up_only_mint.key()
+    )]
+    pub program_up_only_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub payment_token_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        mut,
+        constraint = user_payment_token_account.mint == payment_token_mint.key()
+    )]
+    pub user_payment_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = program_payment_token_account.mint == payment_token_mint.key()
+    )]
+    pub program_payment_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub up_usdc_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        mut,
+        constraint = user_up_usdc_account.mint == up_usdc_mint.key()
+    )]
+    pub user_up_usdc_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = program_up_usdc_account.mint == up_usdc_mint.key()
+    )]
+    pub program_up_usdc_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        seeds = [b"mint_authority"],
+        bump
+    )]
+    /// CHECK: This PDA is derived within the program and only used as a signer; it's safe.
+    pub mint_authority: UncheckedAccount<'info>,
+
+    #[account(
+        seeds = [b"up_usdc_mint_authority"],
+        bump
+    )]
+    /// CHECK: This PDA is derived within the program and only used as a signer; it's safe.
+    pub up_usdc_mint_authority: UncheckedAccount<'info>,
+
+    pub current_mint_authority: Signer<'info>,
+    pub current_up_usdc_authority: Signer<'info>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + 1,
+        seeds = [b"global_state"],
+        bump
+    )]
+    pub global_state: Account<'info, GlobalState>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeFoundersPool<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = FoundersPool::LEN,
+        seeds = [b"founders_pool"],
+        bump
+    )]
+    pub founders_pool: Box<Account<'info, FoundersPool>>,
+
+    /// CHECK: Just a PDA, no need for data validation
+    #[account(
+        seeds = [b"founder_authority"],
+        bump
+    )]
+    pub founder_authority: UncheckedAccount<'info>,
+
+    ///CHECK: PDA that owns the token account
+    #[account(mut)]
+    pub founder_pool_token_account: AccountInfo<'info>,
+
+    pub usdc_mint: Account<'info, Mint>,
+
+    #[account(
+        seeds = [b"metadata", token_mint.key().as_ref()],
+        bump
+    )]
+    pub metadata: Account<'info, TokenMetadata>,
+
+    #[account(mut)]
+    pub token_mint: Account<'info, Mint>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeProtocolState<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + 8 + 8 + 1 + 8 + 8 + 4 + MAX_FEE_TIERS * 32 + 8 + 8 + 8 + 8 + 8
+            + 4 + MAX_REFERRAL_TIERS * 16,
+        seeds = [b"protocol_state"],
+        bump
+    )]
+    pub protocol_state: Box<Account<'info, ProtocolState>>,
+
+    #[account(
+        seeds = [b"metadata", token_mint.key().as_ref()],
+        bump
+    )]
+    pub metadata: Account<'info, TokenMetadata>,
+
+    pub token_mint: Account<'info, Mint>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeTeamTreasury<'info> {
+    #[account(
+        init,
+        payer = authority,
+        token::mint = usdc_mint,
+        token::authority = team_treasury,
+        seeds = [b"team_treasury"],
+        bump
+    )]
+    pub team_treasury: Box<Account<'info, TokenAccount>>,
+
+    #[account(constraint = usdc_mint.key() == metadata.payment_token @ CustomError::InvalidTokenMint)]
+    pub usdc_mint: Account<'info, Mint>,
+
+    #[account(
+        seeds = [b"metadata", token_mint.key().as_ref()],
+        bump
+    )]
+    pub metadata: Account<'info, TokenMetadata>,
+
+    pub token_mint: Account<'info, Mint>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawTeamFees<'info> {
+    pub team: Signer<'info>,
+
+    #[account(mut, seeds = [b"team_treasury"], bump)]
+    pub team_treasury: Box<Account<'info, TokenAccount>>,
+
+    #[account(seeds = [b"fee_split"], bump)]
+    pub fee_split: Box<Account<'info, FeeSplit>>,
+
+    #[account(
+        mut,
+        constraint = destination_token_account.mint == metadata.payment_token @ CustomError::InvalidTokenMint
+    )]
+    pub destination_token_account: Box<Account<'info, TokenAccount>>,
+
+    pub token_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        seeds = [b"metadata", token_mint.key().as_ref()],
+        bump
+    )]
+    pub metadata: Box<Account<'info, TokenMetadata>>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeFeeSplit<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + 8 + 4 + MAX_FEE_RECIPIENTS * (32 + 8 + 8),
+        seeds = [b"fee_split"],
+        bump
+    )]
+    pub fee_split: Box<Account<'info, FeeSplit>>,
+
+    #[account(
+        seeds = [b"metadata", token_mint.key().as_ref()],
+        bump
+    )]
+    pub metadata: Account<'info, TokenMetadata>,
+
+    pub token_mint: Account<'info, Mint>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateFeeSplit<'info> {
+    #[account(mut, seeds = [b"fee_split"], bump)]
+    pub fee_split: Box<Account<'info, FeeSplit>>,
+
+    #[account(
+        seeds = [b"metadata", token_mint.key().as_ref()],
+        bump
+    )]
+    pub metadata: Account<'info, TokenMetadata>,
+
+    pub token_mint: Account<'info, Mint>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimFeeSplit<'info> {
+    pub recipient: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = recipient_token_account.mint == metadata.payment_token @ CustomError::InvalidTokenMint,
+        constraint = recipient_token_account.owner == recipient.key() @ CustomError::InvalidOwner
+    )]
+    pub recipient_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [b"fee_split"], bump)]
+    pub fee_split: Box<Account<'info, FeeSplit>>,
+
+    #[account(mut, seeds = [b"team_treasury"], bump)]
+    pub team_treasury: Box<Account<'info, TokenAccount>>,
+
+    pub token_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        seeds = [b"metadata", token_mint.key().as_ref()],
+        bump
+    )]
+    pub metadata: Box<Account<'info, TokenMetadata>>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeReferralPool<'info> {
+    #[account(
+        init,
+        payer = authority,
+        token::mint = usdc_mint,
+        token::authority = referral_pool,
+        seeds = [b"referral_pool"],
+        bump
+    )]
+    pub referral_pool: Box<Account<'info, TokenAccount>>,
+
+    #[account(constraint = usdc_mint.key() == metadata.payment_token @ CustomError::InvalidTokenMint)]
+    pub usdc_mint: Account<'info, Mint>,
+
+    #[account(
+        seeds = [b"metadata", token_mint.key().as_ref()],
+        bump
+    )]
+    pub metadata: Account<'info, TokenMetadata>,
+
+    pub token_mint: Account<'info, Mint>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct RegisterReferrer<'info> {
+    #[account(mut)]
+    pub referrer: Signer<'info>,
+
+    #[account(
+        init,
+        payer = referrer,
+        space = 8 + 32 + 8 + 8 + 8 + 8,
+        seeds = [b"referral", referrer.key().as_ref()],
+        bump
+    )]
+    pub referral_account: Box<Account<'info, ReferralAccount>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(code: String)]
+pub struct RegisterReferralCode<'info> {
+    #[account(mut)]
+    pub referrer: Signer<'info>,
+
+    #[account(
+        seeds = [b"referral", referrer.key().as_ref()],
+        bump,
+        constraint = referral_account.referrer == referrer.key() @ CustomError::Unauthorized
+    )]
+    pub referral_account: Box<Account<'info, ReferralAccount>>,
+
+    #[account(
+        init,
+        payer = referrer,
+        space = 8 + 32 + 4 + MAX_REFERRAL_CODE_LEN,
+        seeds = [b"ref_code", code.as_bytes()],
+        bump
+    )]
+    pub referral_code: Box<Account<'info, ReferralCode>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimReferralRewards<'info> {
+    pub referrer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"referral", referrer.key().as_ref()],
+        bump,
+        constraint = referral_account.referrer == referrer.key() @ CustomError::Unauthorized
+    )]
+    pub referral_account: Box<Account<'info, ReferralAccount>>,
+
+    #[account(mut, seeds = [b"referral_pool"], bump)]
+    pub referral_pool: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = referrer_token_account.mint == referral_pool.mint @ CustomError::InvalidTokenMint,
+        constraint = referrer_token_account.owner == referrer.key() @ CustomError::InvalidOwner
+    )]
+    pub referrer_token_account: Box<Account<'info, TokenAccount>>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeInsuranceFund<'info> {
+    /// CHECK: Just a PDA, no need for data validation
+    #[account(
+        seeds = [b"insurance_fund"],
+        bump
+    )]
+    pub insurance_authority: UncheckedAccount<'info>,
+
+    ///CHECK: PDA that owns the token account
+    #[account(mut)]
+    pub insurance_fund_token_account: AccountInfo<'info>,
+
+    pub usdc_mint: Account<'info, Mint>,
+
+    #[account(
+        seeds = [b"metadata", token_mint.key().as_ref()],
+        bump
+    )]
+    pub metadata: Account<'info, TokenMetadata>,
+
+    pub token_mint: Account<'info, Mint>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct FundInsurance<'info> {
+    pub funder: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = funder_usdc_account.mint == metadata.payment_token
+    )]
+    pub funder_usdc_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        seeds = [b"insurance_fund"],
+        bump
+    )]
+    /// CHECK: PDA that owns insurance_fund_token_account
+    pub insurance_authority: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        constraint = insurance_fund_token_account.key()
+            == anchor_spl::associated_token::get_associated_token_address(
+                &insurance_authority.key(),
+                &metadata.payment_token
+            )
+    )]
+    pub insurance_fund_token_account: Box<Account<'info, TokenAccount>>,
+
+    pub token_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        seeds = [b"metadata", token_mint.key().as_ref()],
+        bump
+    )]
+    pub metadata: Box<Account<'info, TokenMetadata>>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CoverBadDebt<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = source_token_account.mint == metadata.payment_token
+    )]
+    pub source_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        seeds = [b"insurance_fund"],
+        bump
+    )]
+    /// CHECK: signer for transferring from the insurance fund
+    pub insurance_authority: UncheckedAccount<'info>,
+
+    #[account(
+        seeds = [b"token_account", metadata.payment_token.as_ref()],
+        bump
+    )]
+    /// CHECK: PDA that owns program_payment_token_account
+    pub pool_authority: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        constraint = program_payment_token_account.mint == metadata.payment_token,
+        constraint = program_payment_token_account.key()
+            == anchor_spl::associated_token::get_associated_token_address(
+                &pool_authority.key(),
+                &metadata.payment_token
+            )
+    )]
+    pub program_payment_token_account: Box<Account<'info, TokenAccount>>,
+
+    pub token_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        seeds = [b"metadata", token_mint.key().as_ref()],
+        bump
+    )]
+    pub metadata: Box<Account<'info, TokenMetadata>>,
+
+    #[account(seeds = [b"fee_split"], bump)]
+    pub fee_split: Box<Account<'info, FeeSplit>>,
+
+    #[account(mut, seeds = [b"protocol_state"], bump)]
+    pub protocol_state: Box<Account<'info, ProtocolState>>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateProtocolState<'info> {
+    #[account(mut, seeds = [b"protocol_state"], bump)]
+    pub protocol_state: Box<Account<'info, ProtocolState>>,
+
+    #[account(
+        seeds = [b"metadata", token_mint.key().as_ref()],
+        bump
+    )]
+    pub metadata: Account<'info, TokenMetadata>,
+
+    pub token_mint: Account<'info, Mint>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimFounderShare<'info> {
+    #[account(mut)]
+    pub founder: Signer<'info>,
+
+    #[account(seeds = [b"founders_pool"], bump)]
+    pub founders_pool: Box<Account<'info, FoundersPool>>,
+
+    #[account(
+        mut,
+        seeds = [b"founder", founder.key().as_ref()],
+        bump,
+        constraint = founder_state.founder == founder.key() @ CustomError::NotFounder
+    )]
+    pub founder_state: Box<Account<'info, Founder>>,
+
+    #[account(
+        mut,
+        constraint = founder_token_account.owner == founder.key(),
+        constraint = founder_token_account.mint == founder_pool_token_account.mint,
+        associated_token::mint = founder_pool_token_account.mint,
+        associated_token::authority = founder
+    )]
+    pub founder_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub founder_pool_token_account: Account<'info, TokenAccount>,
+
+    /// CHECK: signer PDA
+    #[account(seeds = [b"founder_authority"], bump)]
+    pub founder_authority: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct DistributeFounderShares<'info> {
+    #[account(seeds = [b"founders_pool"], bump)]
+    pub founders_pool: Box<Account<'info, FoundersPool>>,
+
+    #[account(
+        mut,
+        constraint = founder_pool_token_account.key() == anchor_spl::associated_token::get_associated_token_address(&founder_authority.key(), &founder_pool_token_account.mint)
+    )]
+    pub founder_pool_token_account: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: signer PDA
+    #[account(seeds = [b"founder_authority"], bump)]
+    pub founder_authority: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(payer: Pubkey)]
+pub struct ApproveLockPayer<'info> {
+    #[account(mut)]
+    pub beneficiary: Signer<'info>,
+
+    #[account(
+        init,
+        payer = beneficiary,
+        space = 8 + 32 + 32,
+        seeds = [b"lock_payer", beneficiary.key().as_ref(), payer.as_ref()],
+        bump
+    )]
+    pub approval: Account<'info, LockPayerApproval>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RevokeLockPayer<'info> {
+    #[account(mut)]
+    pub beneficiary: Signer<'info>,
+
+    #[account(
+        mut,
+        close = beneficiary,
+        seeds = [b"lock_payer", beneficiary.key().as_ref(), approval.payer.as_ref()],
+        bump
+    )]
+    pub approval: Account<'info, LockPayerApproval>,
+}
+
+#[derive(Accounts)]
+pub struct BuyAndLockToken<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    /// CHECK: Owner of the lock; pass `user` for a self-owned lock. Any other
+    /// beneficiary must sign or have approved `user` via `approve_lock_payer`
+    pub beneficiary: UncheckedAccount<'info>,
+
+    #[account(seeds = [b"lock_payer", beneficiary.key().as_ref(), user.key().as_ref()], bump)]
+    pub payer_approval: Option<Account<'info, LockPayerApproval>>,
+
+    #[account(init_if_needed, payer = user, space = LockedTokenState::LEN, seeds = [b"locked", beneficiary.key().as_ref()], bump)]
+    pub lock_state: Account<'info, LockedTokenState>,
+
+    #[account(
+        mut,
+        constraint = user_usdc_account.mint == metadata.payment_token,
+        associated_token::mint = metadata.payment_token,
+        associated_token::authority = user
+    )]
+    pub user_usdc_account: Box<Account<'info, TokenAccount>>,
+    
+    #[account(mut, seeds = [b"team_treasury"], bump)]
+    pub team_treasury: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [b"fee_split"], bump)]
+    pub fee_split: Box<Account<'info, FeeSplit>>,
+    
+    #[account(
+        mut,
+        constraint = program_payment_token_account.mint == metadata.payment_token,
+        constraint = program_payment_token_account.key()
+            == anchor_spl::associated_token::get_associated_token_address(
+                &pool_authority.key(),
+                &metadata.payment_token
+            )
+    )]
+    pub program_payment_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub token_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        seeds = [b"mint_authority"],
+        bump
+    )]
+    /// CHECK: only used as signer
+    pub mint_authority: UncheckedAccount<'info>,
+
+    #[account(
+        seeds = [b"metadata", token_mint.key().as_ref()],
+        bump
+    )]
+    pub metadata: Box<Account<'info, TokenMetadata>>,
+
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = vault_authority
+    )]
+    /// CHECK: ATA for vault
+    pub vault_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(seeds = [b"vault", beneficiary.key().as_ref()], bump)]
+    /// CHECK: Vault PDA signer
+    pub vault_authority: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [b"referral", referral_account.referrer.as_ref()], bump)]
+    pub referral_account: Option<Box<Account<'info, ReferralAccount>>>,
+
+    #[account(mut, seeds = [b"referral_pool"], bump)]
+    pub referral_pool: Option<Box<Account<'info, TokenAccount>>>,
+
+    /// `[b"ref_code", code]` entry when buying with a referral code
+    pub referral_code_account: Option<Box<Account<'info, ReferralCode>>>,
+
+    /// Fresh keypair for the optional position receipt NFT
+    #[account(mut)]
+    pub position_mint: Option<Signer<'info>>,
+
+    #[account(mut)]
+    /// CHECK: beneficiary's ATA for position_mint, created in the handler
+    pub receipt_token_account: Option<UncheckedAccount<'info>>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+
+    #[account(mut, seeds = [b"founders_pool"], bump)]
+    pub founders_pool: Box<Account<'info, FoundersPool>>,
+
+    #[account(
+        seeds = [b"founder_authority"],
+        bump
+    )]
+    /// CHECK: PDA used as authority/owner of founder pool ATA
+    pub founder_authority: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub founder_pool_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        seeds = [b"token_account", metadata.payment_token.as_ref()],
+        bump
+    )]
+    /// CHECK: PDA that owns program_payment_token_account
+    pub pool_authority: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub user_up_usdc_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = program_up_usdc_account.mint == metadata.up_usdc_mint,
+        constraint = program_up_usdc_account.key() == anchor_spl::associated_token::get_associated_token_address(&up_pool_authority.key(), &metadata.up_usdc_mint)
+    )]
+    pub program_up_usdc_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub up_usdc_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        seeds = [b"up_usdc_mint_authority"],
+        bump
+    )]
+    /// CHECK: only used as signer
+    pub up_usdc_mint_authority: UncheckedAccount<'info>,
+
+    #[account(
+        seeds = [b"token_account", up_usdc_mint.key().as_ref()],
+        bump
+    )]
+    /// CHECK: PDA that owns program_up_usdc_account
+    pub up_pool_authority: UncheckedAccount<'info>,
+
+    pub associated_token_program: Program<'info, AssociatedToken>,
+
+    #[account(seeds = [b"protocol_state"], bump)]
+    pub protocol_state: Box<Account<'info, ProtocolState>>,
+}
+
+#[derive(Accounts)]
+#[instruction(position_id: u64)]
+pub struct LeverageBuy<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = LeveragePosition::LEN,
+        seeds = [b"leverage", user.key().as_ref(), &position_id.to_le_bytes()],
+        bump
+    )]
+    pub leverage_position: Account<'info, LeveragePosition>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + 32 + 8 + 4 + MAX_LEVERAGE_POSITIONS * 8,
+        seeds = [b"leverage_registry", user.key().as_ref()],
+        bump
+    )]
+    pub leverage_registry: Box<Account<'info, LeverageRegistry>>,
+
+    #[account(
+        mut,
+        constraint = user_usdc_account.mint == metadata.payment_token,
+        associated_token::mint = metadata.payment_token,
+        associated_token::authority = user
+    )]
+    pub user_usdc_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [b"team_treasury"], bump)]
+    pub team_treasury: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [b"fee_split"], bump)]
+    pub fee_split: Box<Account<'info, FeeSplit>>,
+
+    #[account(
+        mut,
+        constraint = program_payment_token_account.mint == metadata.payment_token,
+        constraint = program_payment_token_account.key()
+            == anchor_spl::associated_token::get_associated_token_address(
+                &pool_authority.key(),
+                &metadata.payment_token
+            )
+    )]
+    pub program_payment_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub token_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        seeds = [b"mint_authority"],
+        bump
+    )]
+    /// CHECK: only used as signer
+    pub mint_authority: UncheckedAccount<'info>,
+
+    #[account(
+        seeds = [b"metadata", token_mint.key().as_ref()],
+        bump
+    )]
+    pub metadata: Box<Account<'info, TokenMetadata>>,
+
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = vault_authority,
+        constraint = vault_token_account.mint == metadata.mint
+    )]
+    /// CHECK: ATA for vault
+    pub vault_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(seeds = [b"l_vault", user.key().as_ref(), &position_id.to_le_bytes()], bump)]
+    /// CHECK: Vault PDA signer
+    pub vault_authority: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [b"referral", referral_account.referrer.as_ref()], bump)]
+    pub referral_account: Option<Box<Account<'info, ReferralAccount>>>,
+
+    #[account(mut, seeds = [b"referral_pool"], bump)]
+    pub referral_pool: Option<Box<Account<'info, TokenAccount>>>,
+
+    /// `[b"ref_code", code]` entry when buying with a referral code
+    pub referral_code_account: Option<Box<Account<'info, ReferralCode>>>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+
+    #[account(mut, seeds = [b"founders_pool"], bump)]
+    pub founders_pool: Box<Account<'info, FoundersPool>>,
+
+    #[account(
+        seeds = [b"founder_authority"],
+        bump
+    )]
+    /// CHECK: PDA used as authority/owner of founder pool ATA
+    pub founder_authority: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        constraint = founder_pool_token_account.mint == metadata.payment_token
+    )]
+    pub founder_pool_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        seeds = [b"token_account", metadata.payment_token.as_ref()],
+        bump
+    )]
+    /// CHECK: PDA that owns program_payment_token_account
+    pub pool_authority: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        constraint = user_up_usdc_account.mint == metadata.up_usdc_mint
+    )]
+    pub user_up_usdc_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = program_up_usdc_account.mint == metadata.up_usdc_mint,
+        constraint = program_up_usdc_account.key() == anchor_spl::associated_token::get_associated_token_address(&up_pool_authority.key(), &metadata.up_usdc_mint)
+    )]
+    pub program_up_usdc_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = up_usdc_mint.key() == metadata.up_usdc_mint
+    )]
+    pub up_usdc_mint: Account<'info, Mint>,
+
+    #[account(
+        seeds = [b"up_usdc_mint_authority"],
+        bump
+    )]
+    /// CHECK: only used as signer
+    pub up_usdc_mint_authority: UncheckedAccount<'info>,
+
+    #[account(
+        seeds = [b"token_account", up_usdc_mint.key().as_ref()],
+        bump
+    )]
+    /// CHECK: PDA that owns program_up_usdc_account
+    pub up_pool_authority: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [b"protocol_state"], bump)]
+    pub protocol_state: Box<Account<'info, ProtocolState>>,
+}
+
+#[derive(Accounts)]
+#[instruction(position_id: u64)]
+pub struct LeverageSell<'info> {
+    pub cranker: Signer<'info>,
+
+    #[account(
+        seeds = [b"token_account", metadata.payment_token.as_ref()],
+        bump
+    )]
+    /// CHECK: signer for transferring from program_payment_token_account
+    pub pool_authority: UncheckedAccount<'info>,
+
+    #[account(
+        seeds = [b"metadata", token_mint.key().as_ref()],
+        bump
+    )]
+    pub metadata: Account<'info, TokenMetadata>,
+
+    #[account(mut)]
+    ///CHECK: Used to derive vault PDA; must authorize closing the position
+    pub user: Signer<'info>,
+
+    #[account(mut, seeds = [b"leverage", user.key().as_ref(), &position_id.to_le_bytes()], bump)]
+    pub leverage_position: Account<'info, LeveragePosition>,
+
+    #[account(mut, seeds = [b"leverage_registry", user.key().as_ref()], bump)]
+    pub leverage_registry: Box<Account<'info, LeverageRegistry>>,
+
+    #[account(
+        mut,
+        seeds = [b"l_vault", user.key().as_ref(), &position_id.to_le_bytes()],
+        bump
+    )]
+    /// CHECK: Only used as signer
+    pub vault_authority: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = vault_authority,
+        constraint = vault_token_account.mint == metadata.mint
+    )]
+    pub vault_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = user_usdc_account.mint == metadata.payment_token,
+        associated_token::mint = metadata.payment_token,
+        associated_token::authority = user
+    )]
+    pub user_usdc_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [b"team_treasury"], bump)]
+    pub team_treasury: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [b"fee_split"], bump)]
+    pub fee_split: Box<Account<'info, FeeSplit>>,
+
+    #[account(
+        mut,
+        constraint = program_payment_token_account.mint == metadata.payment_token,
+        constraint = program_payment_token_account.key()
+            == anchor_spl::associated_token::get_associated_token_address(
+                &pool_authority.key(),
+                &metadata.payment_token
+            )
+    )]
+    pub program_payment_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub token_mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = founder_pool_token_account.mint == metadata.payment_token,
+        constraint = founder_pool_token_account.key() == anchor_spl::associated_token::get_associated_token_address(&founder_authority.key(), &metadata.payment_token)
+    )]
+    pub founder_pool_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [b"founders_pool"], bump)]
+    pub founders_pool: Box<Account<'info, FoundersPool>>,
+
+    #[account(
+        seeds = [b"founder_authority"],
+        bump
+    )]
+    /// CHECK: PDA used as authority/owner of founder pool ATA
+    pub founder_authority: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+
+    #[account(
+        mut,
+        constraint = user_up_usdc_account.mint == metadata.up_usdc_mint
+    )]
+    pub user_up_usdc_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = program_up_usdc_account.mint == metadata.up_usdc_mint,
+        constraint = program_up_usdc_account.key() == anchor_spl::associated_token::get_associated_token_address(&up_pool_authority.key(), &metadata.up_usdc_mint)
+    )]
+    pub program_up_usdc_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = up_usdc_mint.key() == metadata.up_usdc_mint
+    )]
+    pub up_usdc_mint: Account<'info, Mint>,
+
+    #[account(
+        seeds = [b"token_account", up_usdc_mint.key().as_ref()],
+        bump
+    )]
+    /// CHECK: PDA used as signer for burning from program_up_usdc_account
+    pub up_pool_authority: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [b"referral", referral_account.referrer.as_ref()], bump)]
+    pub referral_account: Option<Box<Account<'info, ReferralAccount>>>,
+
+    #[account(mut, seeds = [b"referral_pool"], bump)]
+    pub referral_pool: Option<Box<Account<'info, TokenAccount>>>,
+
+    #[account(mut, seeds = [b"protocol_state"], bump)]
+    pub protocol_state: Box<Account<'info, ProtocolState>>,
+}
+
+#[derive(Accounts)]
+#[instruction(position_id: u64)]
+pub struct AddCollateral<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(mut, seeds = [b"leverage", user.key().as_ref(), &position_id.to_le_bytes()], bump)]
+    pub leverage_position: Account<'info, LeveragePosition>,
+
+    #[account(
+        mut,
+        constraint = user_usdc_account.mint == metadata.payment_token,
+        associated_token::mint = metadata.payment_token,
+        associated_token::authority = user
+    )]
+    pub user_usdc_account: Box<Account<'info, TokenAccount>>,
 
-            token::transfer(
-                CpiContext::new_with_signer(
-                    ctx.accounts.token_program.to_account_info(),
-                    Transfer {
-                        from: ctx.accounts.program_payment_token_account.to_account_info(),
-                        to: referral_token_account.to_account_info(),
-                        authority: ctx.accounts.pool_authority.to_account_info(),
-                    },
-                    pool_seeds,
-                ),
-                referral_share,
-            )?;
+    #[account(
+        seeds = [b"token_account", metadata.payment_token.as_ref()],
+        bump
+    )]
+    /// CHECK: PDA that owns program_payment_token_account
+    pub pool_authority: UncheckedAccount<'info>,
 
-            token::transfer(
-                CpiContext::new_with_signer(
-                    ctx.accounts.token_program.to_account_info(),
-                    Transfer {
-                        from: ctx.accounts.program_payment_token_account.to_account_info(),
-                        to: ctx.accounts.deployer_usdc_account.to_account_info(),
-                        authority: ctx.accounts.pool_authority.to_account_info(),
-                    },
-                    pool_seeds,
-                ),
-                deployer_share,
-            )?;
-        } else {
-            token::transfer(
-                CpiContext::new_with_signer(
-                    ctx.accounts.token_program.to_account_info(),
-                    Transfer {
-                        from: ctx.accounts.program_payment_token_account.to_account_info(),
-                        to: ctx.accounts.deployer_usdc_account.to_account_info(),
-                        authority: ctx.accounts.pool_authority.to_account_info(),
-                    },
-                    pool_seeds,
-                ),
-                team_fee,
-            )?;
-        }
+    #[account(
+        mut,
+        constraint = program_payment_token_account.mint == metadata.payment_token,
+        constraint = program_payment_token_account.key()
+            == anchor_spl::associated_token::get_associated_token_address(
+                &pool_authority.key(),
+                &metadata.payment_token
+            )
+    )]
+    pub program_payment_token_account: Box<Account<'info, TokenAccount>>,
 
-        if user_cut > 0 {
-            token::transfer(
-                CpiContext::new_with_signer(
-                    ctx.accounts.token_program.to_account_info(),
-                    Transfer {
-                        from: ctx.accounts.program_payment_token_account.to_account_info(),
-                        to: ctx.accounts.user_usdc_account.to_account_info(),
-                        authority: ctx.accounts.pool_authority.to_account_info(),
-                    },
-                    pool_seeds,
-                ),
-                user_cut,
-            )?;
-        }
+    pub token_mint: Box<Account<'info, Mint>>,
 
-        leverage_position.initialized = false;
-        leverage_position.amount_minted = 0;
-        leverage_position.amount_borrowed = 0;
-        leverage_position.amount_user_paid = 0;
+    #[account(
+        seeds = [b"metadata", token_mint.key().as_ref()],
+        bump
+    )]
+    pub metadata: Box<Account<'info, TokenMetadata>>,
 
-        Ok(())
-    }
-}
+    #[account(mut, seeds = [b"protocol_state"], bump)]
+    pub protocol_state: Box<Account<'info, ProtocolState>>,
 
-pub fn get_lock_fee_config(lock_hour: u64) -> LockFeeConfig {
-    match lock_hour {
-        1 => LockFeeConfig {
-            liquidity_bps: 150,
-            team_bps: 75,
-            founder_bps: 25,
-        },
-        2 => LockFeeConfig {
-            liquidity_bps: 225,
-            team_bps: 100,
-            founder_bps: 25,
-        },
-        3 => LockFeeConfig {
-            liquidity_bps: 300,
-            team_bps: 125,
-            founder_bps: 25,
-        },
-        4 => LockFeeConfig {
-            liquidity_bps: 375,
-            team_bps: 150,
-            founder_bps: 25,
-        },
-        6 => LockFeeConfig {
-            liquidity_bps: 450,
-            team_bps: 175,
-            founder_bps: 25,
-        },
-        8 => LockFeeConfig {
-            liquidity_bps: 550,
-            team_bps: 200,
-            founder_bps: 25,
-        },
-        12 => LockFeeConfig {
-            liquidity_bps: 725,
-            team_bps: 250,
-            founder_bps: 25,
-        },
-        _ => LockFeeConfig {
-            liquidity_bps: 925,
-            team_bps: 300,
-            founder_bps: 25,
-        },
-    }
+    pub token_program: Program<'info, Token>,
 }
 
 #[derive(Accounts)]
-pub struct Initialize<'info> {
-    #[account(mut)]
-    pub up_only_mint: Box<Account<'info, Mint>>,
+#[instruction(position_id: u64)]
+pub struct EarlyCloseLeverage<'info> {
+    pub cranker: Signer<'info>,
 
     #[account(
-        init,
-        payer = authority,
-        space = 8 + 4 + 6 + 4 + 2 + 160 + 32 + 1,
-        seeds = [b"metadata", up_only_mint.key().as_ref()],
+        seeds = [b"token_account", metadata.payment_token.as_ref()],
+        bump
+    )]
+    /// CHECK: signer for transferring from program_payment_token_account
+    pub pool_authority: UncheckedAccount<'info>,
+
+    #[account(
+        seeds = [b"metadata", token_mint.key().as_ref()],
         bump
     )]
     pub metadata: Account<'info, TokenMetadata>,
 
+    #[account(mut)]
+    ///CHECK: Used to derive vault PDA; must authorize closing the position
+    pub user: Signer<'info>,
+
+    #[account(mut, seeds = [b"leverage", user.key().as_ref(), &position_id.to_le_bytes()], bump)]
+    pub leverage_position: Account<'info, LeveragePosition>,
+
+    #[account(mut, seeds = [b"leverage_registry", user.key().as_ref()], bump)]
+    pub leverage_registry: Box<Account<'info, LeverageRegistry>>,
+
     #[account(
         mut,
-        constraint = user_up_only_account.mint == up_only_mint.key()
+        seeds = [b"l_vault", user.key().as_ref(), &position_id.to_le_bytes()],
+        bump
     )]
-    pub user_up_only_account: Box<Account<'info, TokenAccount>>,
+    /// CHECK: Only used as signer
+    pub vault_authority: UncheckedAccount<'info>,
 
     #[account(
         mut,
-        constraint = program_up_only_account.mint == up_only_mint.key()
+        associated_token::mint = token_mint,
+        associated_token::authority = vault_authority,
+        constraint = vault_token_account.mint == metadata.mint
     )]
-    pub program_up_only_account: Box<Account<'info, TokenAccount>>,
-
-    #[account(mut)]
-    pub payment_token_mint: Box<Account<'info, Mint>>,
+    pub vault_token_account: Box<Account<'info, TokenAccount>>,
 
     #[account(
         mut,
-        constraint = user_payment_token_account.mint == payment_token_mint.key()
+        constraint = user_usdc_account.mint == metadata.payment_token,
+        associated_token::mint = metadata.payment_token,
+        associated_token::authority = user
     )]
-    pub user_payment_token_account: Box<Account<'info, TokenAccount>>,
+    pub user_usdc_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [b"team_treasury"], bump)]
+    pub team_treasury: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [b"fee_split"], bump)]
+    pub fee_split: Box<Account<'info, FeeSplit>>,
 
     #[account(
         mut,
-        constraint = program_payment_token_account.mint == payment_token_mint.key()
+        constraint = program_payment_token_account.mint == metadata.payment_token,
+        constraint = program_payment_token_account.key()
+            == anchor_spl::associated_token::get_associated_token_address(
+                &pool_authority.key(),
+                &metadata.payment_token
+            )
     )]
     pub program_payment_token_account: Box<Account<'info, TokenAccount>>,
 
     #[account(mut)]
-    pub up_usdc_mint: Box<Account<'info, Mint>>,
+    pub token_mint: Account<'info, Mint>,
 
     #[account(
         mut,
-        constraint = user_up_usdc_account.mint == up_usdc_mint.key()
+        constraint = founder_pool_token_account.mint == metadata.payment_token,
+        constraint = founder_pool_token_account.key() == anchor_spl::associated_token::get_associated_token_address(&founder_authority.key(), &metadata.payment_token)
     )]
-    pub user_up_usdc_account: Box<Account<'info, TokenAccount>>,
+    pub founder_pool_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [b"founders_pool"], bump)]
+    pub founders_pool: Box<Account<'info, FoundersPool>>,
+
+    #[account(
+        seeds = [b"founder_authority"],
+        bump
+    )]
+    /// CHECK: PDA used as authority/owner of founder pool ATA
+    pub founder_authority: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
 
     #[account(
         mut,
-        constraint = program_up_usdc_account.mint == up_usdc_mint.key()
+        constraint = program_up_usdc_account.mint == metadata.up_usdc_mint,
+        constraint = program_up_usdc_account.key() == anchor_spl::associated_token::get_associated_token_address(&up_pool_authority.key(), &metadata.up_usdc_mint)
     )]
     pub program_up_usdc_account: Box<Account<'info, TokenAccount>>,
 
     #[account(
-        seeds = [b"mint_authority"],
-        bump
+        mut,
+        constraint = up_usdc_mint.key() == metadata.up_usdc_mint
     )]
-    /// CHECK: This PDA is derived within the program and only used as a signer; it's safe.
-    pub mint_authority: UncheckedAccount<'info>,
+    pub up_usdc_mint: Account<'info, Mint>,
 
     #[account(
-        seeds = [b"up_usdc_mint_authority"],
+        seeds = [b"token_account", up_usdc_mint.key().as_ref()],
         bump
     )]
-    /// CHECK: This PDA is derived within the program and only used as a signer; it's safe.
-    pub up_usdc_mint_authority: UncheckedAccount<'info>,
-
-    pub current_mint_authority: Signer<'info>,
-    pub current_up_usdc_authority: Signer<'info>,
+    /// CHECK: PDA used as signer for burning from program_up_usdc_account
+    pub up_pool_authority: UncheckedAccount<'info>,
 
-    #[account(mut)]
-    pub authority: Signer<'info>,
+    #[account(mut, seeds = [b"referral", referral_account.referrer.as_ref()], bump)]
+    pub referral_account: Option<Box<Account<'info, ReferralAccount>>>,
 
-    #[account(
-        init_if_needed,
-        payer = authority,
-        space = 8 + 1,
-        seeds = [b"global_state"],
-        bump
-    )]
-    pub global_state: Account<'info, GlobalState>,
+    #[account(mut, seeds = [b"referral_pool"], bump)]
+    pub referral_pool: Option<Box<Account<'info, TokenAccount>>>,
 
-    pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
-    pub rent: Sysvar<'info, Rent>,
+    #[account(mut, seeds = [b"protocol_state"], bump)]
+    pub protocol_state: Box<Account<'info, ProtocolState>>,
 }
 
 #[derive(Accounts)]
-pub struct InitializeFoundersPool<'info> {
+#[instruction(position_id: u64)]
+pub struct SetLeverageOrder<'info> {
+    pub user: Signer<'info>,
+
     #[account(
-        init,
-        payer = authority,
-        space = 8 + 8 + 1924 + 484 + 1,
-        seeds = [b"founders_pool"],
+        mut,
+        seeds = [b"leverage", user.key().as_ref(), &position_id.to_le_bytes()],
         bump
     )]
-    pub founders_pool: Box<Account<'info, FoundersPool>>,
+    pub leverage_position: Account<'info, LeveragePosition>,
+}
+
+#[derive(Accounts)]
+#[instruction(position_id: u64)]
+pub struct ObservePrice<'info> {
+    /// CHECK: Owner of the position; only used to derive the position PDA
+    pub user: UncheckedAccount<'info>,
 
-    /// CHECK: Just a PDA, no need for data validation
     #[account(
-        seeds = [b"founder_authority"],
+        mut,
+        seeds = [b"leverage", user.key().as_ref(), &position_id.to_le_bytes()],
         bump
     )]
-    pub founder_authority: UncheckedAccount<'info>,
-
-    ///CHECK: PDA that owns the token account
-    #[account(mut)]
-    pub founder_pool_token_account: AccountInfo<'info>,
-
-    pub usdc_mint: Account<'info, Mint>,
+    pub leverage_position: Account<'info, LeveragePosition>,
 
     #[account(
         seeds = [b"metadata", token_mint.key().as_ref()],
@@ -1485,51 +5436,76 @@ pub struct InitializeFoundersPool<'info> {
     )]
     pub metadata: Account<'info, TokenMetadata>,
 
-    #[account(mut)]
     pub token_mint: Account<'info, Mint>,
 
-    #[account(mut)]
-    pub authority: Signer<'info>,
+    #[account(
+        constraint = program_up_usdc_account.mint == metadata.up_usdc_mint,
+        constraint = program_up_usdc_account.key() == anchor_spl::associated_token::get_associated_token_address(&up_pool_authority.key(), &metadata.up_usdc_mint)
+    )]
+    pub program_up_usdc_account: Box<Account<'info, TokenAccount>>,
 
-    pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
+    #[account(
+        seeds = [b"token_account", metadata.up_usdc_mint.as_ref()],
+        bump
+    )]
+    /// CHECK: PDA owning program_up_usdc_account
+    pub up_pool_authority: UncheckedAccount<'info>,
 }
 
 #[derive(Accounts)]
-pub struct ClaimFounderShare<'info> {
-    #[account(mut)]
-    pub founder: Signer<'info>,
-
-    #[account(mut, seeds = [b"founders_pool"], bump)]
-    pub founders_pool: Box<Account<'info, FoundersPool>>,
+#[instruction(position_id: u64)]
+pub struct ExecuteLeverageOrder<'info> {
+    pub keeper: Signer<'info>,
 
     #[account(
         mut,
-        constraint = founder_token_account.owner == founder.key(),
-        constraint = founder_token_account.mint == founder_pool_token_account.mint,
-        associated_token::mint = founder_pool_token_account.mint,
-        associated_token::authority = founder
+        constraint = keeper_usdc_account.mint == metadata.payment_token,
+        constraint = keeper_usdc_account.owner == keeper.key() @ CustomError::InvalidOwner
     )]
-    pub founder_token_account: Account<'info, TokenAccount>,
+    pub keeper_usdc_account: Box<Account<'info, TokenAccount>>,
 
-    #[account(mut)]
-    pub founder_pool_token_account: Account<'info, TokenAccount>,
+    #[account(
+        seeds = [b"token_account", metadata.payment_token.as_ref()],
+        bump
+    )]
+    /// CHECK: signer for transferring from program_payment_token_account
+    pub pool_authority: UncheckedAccount<'info>,
 
-    /// CHECK: signer PDA
-    #[account(seeds = [b"founder_authority"], bump)]
-    pub founder_authority: UncheckedAccount<'info>,
+    #[account(
+        seeds = [b"metadata", token_mint.key().as_ref()],
+        bump
+    )]
+    pub metadata: Account<'info, TokenMetadata>,
 
-    pub token_program: Program<'info, Token>,
-}
+    /// CHECK: Owner of the position; only used to derive the position and vault PDAs
+    pub user: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"leverage", user.key().as_ref(), &position_id.to_le_bytes()],
+        bump,
+        constraint = leverage_position.user == user.key() @ CustomError::Unauthorized
+    )]
+    pub leverage_position: Account<'info, LeveragePosition>,
 
-#[derive(Accounts)]
-pub struct BuyAndLockToken<'info> {
-    #[account(mut)]
-    pub user: Signer<'info>,
+    #[account(mut, seeds = [b"leverage_registry", user.key().as_ref()], bump)]
+    pub leverage_registry: Box<Account<'info, LeverageRegistry>>,
 
-    #[account(init_if_needed, payer = user, space = 8 + 32 + 8 + 8 + 33 + 1 + 8, seeds = [b"locked", user.key().as_ref()], bump)]
-    pub lock_state: Account<'info, LockedTokenState>,
+    #[account(
+        mut,
+        seeds = [b"l_vault", user.key().as_ref(), &position_id.to_le_bytes()],
+        bump
+    )]
+    /// CHECK: Only used as signer
+    pub vault_authority: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = vault_authority,
+        constraint = vault_token_account.mint == metadata.mint
+    )]
+    pub vault_token_account: Box<Account<'info, TokenAccount>>,
 
     #[account(
         mut,
@@ -1538,18 +5514,13 @@ pub struct BuyAndLockToken<'info> {
         associated_token::authority = user
     )]
     pub user_usdc_account: Box<Account<'info, TokenAccount>>,
-    
-    #[account(
-        mut,
-        constraint = deployer_usdc_account.mint == metadata.payment_token,
-        constraint = deployer_usdc_account.key()
-            == anchor_spl::associated_token::get_associated_token_address(
-                &metadata.team,
-                &metadata.payment_token
-            )
-    )]
-    pub deployer_usdc_account: Box<Account<'info, TokenAccount>>,
-    
+
+    #[account(mut, seeds = [b"team_treasury"], bump)]
+    pub team_treasury: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [b"fee_split"], bump)]
+    pub fee_split: Box<Account<'info, FeeSplit>>,
+
     #[account(
         mut,
         constraint = program_payment_token_account.mint == metadata.payment_token,
@@ -1562,39 +5533,14 @@ pub struct BuyAndLockToken<'info> {
     pub program_payment_token_account: Box<Account<'info, TokenAccount>>,
 
     #[account(mut)]
-    pub token_mint: Box<Account<'info, Mint>>,
-
-    #[account(
-        seeds = [b"mint_authority"],
-        bump
-    )]
-    /// CHECK: only used as signer
-    pub mint_authority: UncheckedAccount<'info>,
-
-    #[account(
-        seeds = [b"metadata", token_mint.key().as_ref()],
-        bump
-    )]
-    pub metadata: Box<Account<'info, TokenMetadata>>,
+    pub token_mint: Account<'info, Mint>,
 
     #[account(
         mut,
-        associated_token::mint = token_mint,
-        associated_token::authority = vault_authority
+        constraint = founder_pool_token_account.mint == metadata.payment_token,
+        constraint = founder_pool_token_account.key() == anchor_spl::associated_token::get_associated_token_address(&founder_authority.key(), &metadata.payment_token)
     )]
-    /// CHECK: ATA for vault
-    pub vault_token_account: Box<Account<'info, TokenAccount>>,
-
-    #[account(seeds = [b"vault", user.key().as_ref()], bump)]
-    /// CHECK: Vault PDA signer
-    pub vault_authority: UncheckedAccount<'info>,
-
-    #[account(mut)]
-    pub referral_usdc_account: Option<Account<'info, TokenAccount>>,
-
-    pub token_program: Program<'info, Token>,
-    pub system_program: Program<'info, System>,
-    pub rent: Sysvar<'info, Rent>,
+    pub founder_pool_token_account: Box<Account<'info, TokenAccount>>,
 
     #[account(mut, seeds = [b"founders_pool"], bump)]
     pub founders_pool: Box<Account<'info, FoundersPool>>,
@@ -1606,18 +5552,7 @@ pub struct BuyAndLockToken<'info> {
     /// CHECK: PDA used as authority/owner of founder pool ATA
     pub founder_authority: UncheckedAccount<'info>,
 
-    #[account(mut)]
-    pub founder_pool_token_account: Box<Account<'info, TokenAccount>>,
-
-    #[account(
-        seeds = [b"token_account", metadata.payment_token.as_ref()],
-        bump
-    )]
-    /// CHECK: PDA that owns program_payment_token_account
-    pub pool_authority: UncheckedAccount<'info>,
-
-    #[account(mut)]
-    pub user_up_usdc_account: Box<Account<'info, TokenAccount>>,
+    pub token_program: Program<'info, Token>,
 
     #[account(
         mut,
@@ -1626,80 +5561,65 @@ pub struct BuyAndLockToken<'info> {
     )]
     pub program_up_usdc_account: Box<Account<'info, TokenAccount>>,
 
-    #[account(mut)]
-    pub up_usdc_mint: Box<Account<'info, Mint>>,
-
     #[account(
-        seeds = [b"up_usdc_mint_authority"],
-        bump
+        mut,
+        constraint = up_usdc_mint.key() == metadata.up_usdc_mint
     )]
-    /// CHECK: only used as signer
-    pub up_usdc_mint_authority: UncheckedAccount<'info>,
+    pub up_usdc_mint: Account<'info, Mint>,
 
     #[account(
         seeds = [b"token_account", up_usdc_mint.key().as_ref()],
         bump
     )]
-    /// CHECK: PDA that owns program_up_usdc_account
+    /// CHECK: PDA used as signer for burning from program_up_usdc_account
     pub up_pool_authority: UncheckedAccount<'info>,
 
-    pub associated_token_program: Program<'info, AssociatedToken>,
+    #[account(mut, seeds = [b"referral", referral_account.referrer.as_ref()], bump)]
+    pub referral_account: Option<Box<Account<'info, ReferralAccount>>>,
+
+    #[account(mut, seeds = [b"referral_pool"], bump)]
+    pub referral_pool: Option<Box<Account<'info, TokenAccount>>>,
+
+    #[account(mut, seeds = [b"protocol_state"], bump)]
+    pub protocol_state: Box<Account<'info, ProtocolState>>,
 }
 
 #[derive(Accounts)]
-pub struct LeverageBuy<'info> {
+pub struct ClaimLockedTokens<'info> {
+    /// Lock owner, or the current holder of the position receipt
     #[account(mut)]
-    pub user: Signer<'info>,
+    pub holder: Signer<'info>,
 
     #[account(
-        init_if_needed,
-        payer = user,
-        space = 8 + 32 + 8 + 8 + 8 + 33 + 1 + 8 + 8,
-        seeds = [b"leverage", user.key().as_ref()],
+        seeds = [b"token_account", metadata.payment_token.as_ref()],
         bump
     )]
-    pub leverage_position: Account<'info, LeveragePosition>,
+    /// CHECK: signer for transferring from program_payment_token_account
+    pub pool_authority: UncheckedAccount<'info>,
 
     #[account(
-        mut,
-        constraint = user_usdc_account.mint == metadata.payment_token,
-        associated_token::mint = metadata.payment_token,
-        associated_token::authority = user
+        seeds = [b"metadata", token_mint.key().as_ref()],
+        bump
     )]
-    pub user_usdc_account: Box<Account<'info, TokenAccount>>,
+    pub metadata: Account<'info, TokenMetadata>,
 
-    #[account(
-        mut,
-        constraint = deployer_usdc_account.mint == metadata.payment_token
-    )]
-    pub deployer_usdc_account: Box<Account<'info, TokenAccount>>,
+    ///CHECK: Lock owner; used to derive the lock and vault PDAs
+    pub user: UncheckedAccount<'info>,
 
     #[account(
         mut,
-        constraint = program_payment_token_account.mint == metadata.payment_token,
-        constraint = program_payment_token_account.key()
-            == anchor_spl::associated_token::get_associated_token_address(
-                &pool_authority.key(),
-                &metadata.payment_token
-            )
-    )]
-    pub program_payment_token_account: Box<Account<'info, TokenAccount>>,
-
-    #[account(mut)]
-    pub token_mint: Box<Account<'info, Mint>>,
-
-    #[account(
-        seeds = [b"mint_authority"],
+        seeds = [b"locked", user.key().as_ref()],
         bump
     )]
-    /// CHECK: only used as signer
-    pub mint_authority: UncheckedAccount<'info>,
+    pub lock_state: Account<'info, LockedTokenState>,
 
     #[account(
-        seeds = [b"metadata", token_mint.key().as_ref()],
+        mut,
+        seeds = [b"vault", user.key().as_ref()],
         bump
     )]
-    pub metadata: Box<Account<'info, TokenMetadata>>,
+    /// CHECK: Only used as signer
+    pub vault_authority: UncheckedAccount<'info>,
 
     #[account(
         mut,
@@ -1707,50 +5627,55 @@ pub struct LeverageBuy<'info> {
         associated_token::authority = vault_authority,
         constraint = vault_token_account.mint == metadata.mint
     )]
-    /// CHECK: ATA for vault
-    pub vault_token_account: Box<Account<'info, TokenAccount>>,
-
-    #[account(seeds = [b"l_vault", user.key().as_ref()], bump)]
-    /// CHECK: Vault PDA signer
-    pub vault_authority: UncheckedAccount<'info>,
+    pub vault_token_account: Account<'info, TokenAccount>,
 
     #[account(
         mut,
-        constraint = referral_usdc_account.key() == anchor_lang::solana_program::system_program::ID || referral_usdc_account.mint == metadata.payment_token
+        constraint = user_usdc_account.mint == metadata.payment_token,
+        associated_token::mint = metadata.payment_token,
+        associated_token::authority = holder
     )]
-    pub referral_usdc_account: Option<Account<'info, TokenAccount>>,
+    pub user_usdc_account: Box<Account<'info, TokenAccount>>,
 
-    pub token_program: Program<'info, Token>,
-    pub system_program: Program<'info, System>,
-    pub rent: Sysvar<'info, Rent>,
+    #[account(mut, seeds = [b"team_treasury"], bump)]
+    pub team_treasury: Box<Account<'info, TokenAccount>>,
 
-    #[account(mut, seeds = [b"founders_pool"], bump)]
-    pub founders_pool: Box<Account<'info, FoundersPool>>,
+    #[account(mut, seeds = [b"fee_split"], bump)]
+    pub fee_split: Box<Account<'info, FeeSplit>>,
+
+    #[account(mut, seeds = [b"referral", referral_account.referrer.as_ref()], bump)]
+    pub referral_account: Option<Box<Account<'info, ReferralAccount>>>,
+
+    #[account(mut, seeds = [b"referral_pool"], bump)]
+    pub referral_pool: Option<Box<Account<'info, TokenAccount>>>,
 
     #[account(
-        seeds = [b"founder_authority"],
-        bump
+        mut,
+        constraint = program_payment_token_account.mint == metadata.payment_token
     )]
-    /// CHECK: PDA used as authority/owner of founder pool ATA
-    pub founder_authority: UncheckedAccount<'info>,
+    pub program_payment_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub token_mint: Account<'info, Mint>,
+
     #[account(
         mut,
-        constraint = founder_pool_token_account.mint == metadata.payment_token
+        constraint = founder_pool_token_account.mint == metadata.payment_token,
+        constraint = founder_pool_token_account.key() == anchor_spl::associated_token::get_associated_token_address(&founder_authority.key(), &metadata.payment_token)
     )]
     pub founder_pool_token_account: Box<Account<'info, TokenAccount>>,
 
+    #[account(mut, seeds = [b"founders_pool"], bump)]
+    pub founders_pool: Box<Account<'info, FoundersPool>>,
+
     #[account(
-        seeds = [b"token_account", metadata.payment_token.as_ref()],
+        seeds = [b"founder_authority"],
         bump
     )]
-    /// CHECK: PDA that owns program_payment_token_account
-    pub pool_authority: UncheckedAccount<'info>,
+    /// CHECK: PDA used as authority/owner of founder pool ATA
+    pub founder_authority: UncheckedAccount<'info>,
 
-    #[account(
-        mut,
-        constraint = user_up_usdc_account.mint == metadata.up_usdc_mint
-    )]
-    pub user_up_usdc_account: Box<Account<'info, TokenAccount>>,
+    pub token_program: Program<'info, Token>,
 
     #[account(
         mut,
@@ -1765,48 +5690,59 @@ pub struct LeverageBuy<'info> {
     )]
     pub up_usdc_mint: Account<'info, Mint>,
 
-    #[account(
-        seeds = [b"up_usdc_mint_authority"],
-        bump
-    )]
-    /// CHECK: only used as signer
-    pub up_usdc_mint_authority: UncheckedAccount<'info>,
-
     #[account(
         seeds = [b"token_account", up_usdc_mint.key().as_ref()],
         bump
     )]
-    /// CHECK: PDA that owns program_up_usdc_account
-    pub up_pool_authority: UncheckedAccount<'info>,
+    /// CHECK: signer for burning from program_up_usdc_account
+   pub up_pool_authority: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub position_mint: Option<Box<Account<'info, Mint>>>,
+
+    #[account(mut)]
+    pub receipt_token_account: Option<Box<Account<'info, TokenAccount>>>,
+
+    #[account(seeds = [b"protocol_state"], bump)]
+    pub protocol_state: Box<Account<'info, ProtocolState>>,
 }
 
 #[derive(Accounts)]
-pub struct LeverageSell<'info> {
-    pub cranker: Signer<'info>,
+pub struct SetLockOptions<'info> {
+    pub user: Signer<'info>,
 
     #[account(
-        seeds = [b"token_account", metadata.payment_token.as_ref()],
+        mut,
+        seeds = [b"locked", user.key().as_ref()],
         bump
     )]
-    /// CHECK: signer for transferring from program_payment_token_account
-    pub pool_authority: UncheckedAccount<'info>,
+    pub lock_state: Account<'info, LockedTokenState>,
+}
+
+#[derive(Accounts)]
+pub struct CrankSettle<'info> {
+    pub keeper: Signer<'info>,
 
     #[account(
-        seeds = [b"metadata", token_mint.key().as_ref()],
-        bump
+        mut,
+        constraint = keeper_usdc_account.mint == metadata.payment_token,
+        constraint = keeper_usdc_account.owner == keeper.key() @ CustomError::InvalidOwner
     )]
-    pub metadata: Account<'info, TokenMetadata>,
-
-    #[account(mut)]
-    ///CHECK: Used to derive vault PDA; must authorize closing the position
-    pub user: Signer<'info>,
+    pub keeper_usdc_account: Box<Account<'info, TokenAccount>>,
 
-    #[account(mut, seeds = [b"leverage", user.key().as_ref()], bump)]
-    pub leverage_position: Account<'info, LeveragePosition>,
+    /// CHECK: Owner of the lock; only used to derive the lock and vault PDAs
+    pub user: UncheckedAccount<'info>,
 
     #[account(
         mut,
-        seeds = [b"l_vault", user.key().as_ref()],
+        seeds = [b"locked", user.key().as_ref()],
+        bump,
+        constraint = lock_state.user == user.key() @ CustomError::Unauthorized
+    )]
+    pub lock_state: Account<'info, LockedTokenState>,
+
+    #[account(
+        seeds = [b"vault", user.key().as_ref()],
         bump
     )]
     /// CHECK: Only used as signer
@@ -1828,12 +5764,24 @@ pub struct LeverageSell<'info> {
     )]
     pub user_usdc_account: Box<Account<'info, TokenAccount>>,
 
+    #[account(mut, seeds = [b"team_treasury"], bump)]
+    pub team_treasury: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [b"fee_split"], bump)]
+    pub fee_split: Box<Account<'info, FeeSplit>>,
+
+    #[account(mut, seeds = [b"referral", referral_account.referrer.as_ref()], bump)]
+    pub referral_account: Option<Box<Account<'info, ReferralAccount>>>,
+
+    #[account(mut, seeds = [b"referral_pool"], bump)]
+    pub referral_pool: Option<Box<Account<'info, TokenAccount>>>,
+
     #[account(
-        mut,
-        constraint = deployer_usdc_account.mint == metadata.payment_token,
-        constraint = deployer_usdc_account.key() == anchor_spl::associated_token::get_associated_token_address(&metadata.team, &metadata.payment_token)
+        seeds = [b"token_account", metadata.payment_token.as_ref()],
+        bump
     )]
-    pub deployer_usdc_account: Box<Account<'info, TokenAccount>>,
+    /// CHECK: signer for transferring from program_payment_token_account
+    pub pool_authority: UncheckedAccount<'info>,
 
     #[account(
         mut,
@@ -1847,7 +5795,13 @@ pub struct LeverageSell<'info> {
     pub program_payment_token_account: Box<Account<'info, TokenAccount>>,
 
     #[account(mut)]
-    pub token_mint: Account<'info, Mint>,
+    pub token_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        seeds = [b"metadata", token_mint.key().as_ref()],
+        bump
+    )]
+    pub metadata: Box<Account<'info, TokenMetadata>>,
 
     #[account(
         mut,
@@ -1866,14 +5820,6 @@ pub struct LeverageSell<'info> {
     /// CHECK: PDA used as authority/owner of founder pool ATA
     pub founder_authority: UncheckedAccount<'info>,
 
-    pub token_program: Program<'info, Token>,
-
-    #[account(
-        mut,
-        constraint = user_up_usdc_account.mint == metadata.up_usdc_mint
-    )]
-    pub user_up_usdc_account: Box<Account<'info, TokenAccount>>,
-
     #[account(
         mut,
         constraint = program_up_usdc_account.mint == metadata.up_usdc_mint,
@@ -1885,49 +5831,44 @@ pub struct LeverageSell<'info> {
         mut,
         constraint = up_usdc_mint.key() == metadata.up_usdc_mint
     )]
-    pub up_usdc_mint: Account<'info, Mint>,
+    pub up_usdc_mint: Box<Account<'info, Mint>>,
 
     #[account(
         seeds = [b"token_account", up_usdc_mint.key().as_ref()],
         bump
     )]
-    /// CHECK: PDA used as signer for burning from program_up_usdc_account
+    /// CHECK: signer for burning from program_up_usdc_account
     pub up_pool_authority: UncheckedAccount<'info>,
 
-    #[account(
-        mut,
-        constraint = referral_usdc_account.key() == anchor_lang::solana_program::system_program::ID || referral_usdc_account.mint == metadata.payment_token
-    )]
-    pub referral_usdc_account: Option<Account<'info, TokenAccount>>,
+    pub token_program: Program<'info, Token>,
+
+    #[account(seeds = [b"protocol_state"], bump)]
+    pub protocol_state: Box<Account<'info, ProtocolState>>,
 }
 
 #[derive(Accounts)]
-pub struct EarlyCloseLeverage<'info> {
-    pub cranker: Signer<'info>,
+pub struct Rollover<'info> {
+    pub authority: Signer<'info>,
 
     #[account(
-        seeds = [b"token_account", metadata.payment_token.as_ref()],
-        bump
+        mut,
+        constraint = keeper_usdc_account.mint == metadata.payment_token
     )]
-    /// CHECK: signer for transferring from program_payment_token_account
-    pub pool_authority: UncheckedAccount<'info>,
+    pub keeper_usdc_account: Option<Box<Account<'info, TokenAccount>>>,
+
+    /// CHECK: Owner of the lock; only used to derive the lock and vault PDAs
+    pub user: UncheckedAccount<'info>,
 
     #[account(
-        seeds = [b"metadata", token_mint.key().as_ref()],
-        bump
+        mut,
+        seeds = [b"locked", user.key().as_ref()],
+        bump,
+        constraint = lock_state.user == user.key() @ CustomError::Unauthorized
     )]
-    pub metadata: Account<'info, TokenMetadata>,
-
-    #[account(mut)]
-    ///CHECK: Used to derive vault PDA; must authorize closing the position
-    pub user: Signer<'info>,
-
-    #[account(mut, seeds = [b"leverage", user.key().as_ref()], bump)]
-    pub leverage_position: Account<'info, LeveragePosition>,
+    pub lock_state: Account<'info, LockedTokenState>,
 
     #[account(
-        mut,
-        seeds = [b"l_vault", user.key().as_ref()],
+        seeds = [b"vault", user.key().as_ref()],
         bump
     )]
     /// CHECK: Only used as signer
@@ -1941,20 +5882,24 @@ pub struct EarlyCloseLeverage<'info> {
     )]
     pub vault_token_account: Box<Account<'info, TokenAccount>>,
 
-    #[account(
-        mut,
-        constraint = user_usdc_account.mint == metadata.payment_token,
-        associated_token::mint = metadata.payment_token,
-        associated_token::authority = user
-    )]
-    pub user_usdc_account: Box<Account<'info, TokenAccount>>,
+    #[account(mut, seeds = [b"team_treasury"], bump)]
+    pub team_treasury: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [b"fee_split"], bump)]
+    pub fee_split: Box<Account<'info, FeeSplit>>,
+
+    #[account(mut, seeds = [b"referral", referral_account.referrer.as_ref()], bump)]
+    pub referral_account: Option<Box<Account<'info, ReferralAccount>>>,
+
+    #[account(mut, seeds = [b"referral_pool"], bump)]
+    pub referral_pool: Option<Box<Account<'info, TokenAccount>>>,
 
     #[account(
-        mut,
-        constraint = deployer_usdc_account.mint == metadata.payment_token,
-        constraint = deployer_usdc_account.key() == anchor_spl::associated_token::get_associated_token_address(&metadata.team, &metadata.payment_token)
+        seeds = [b"token_account", metadata.payment_token.as_ref()],
+        bump
     )]
-    pub deployer_usdc_account: Box<Account<'info, TokenAccount>>,
+    /// CHECK: signer for transferring from program_payment_token_account
+    pub pool_authority: UncheckedAccount<'info>,
 
     #[account(
         mut,
@@ -1968,7 +5913,13 @@ pub struct EarlyCloseLeverage<'info> {
     pub program_payment_token_account: Box<Account<'info, TokenAccount>>,
 
     #[account(mut)]
-    pub token_mint: Account<'info, Mint>,
+    pub token_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        seeds = [b"metadata", token_mint.key().as_ref()],
+        bump
+    )]
+    pub metadata: Box<Account<'info, TokenMetadata>>,
 
     #[account(
         mut,
@@ -1987,8 +5938,6 @@ pub struct EarlyCloseLeverage<'info> {
     /// CHECK: PDA used as authority/owner of founder pool ATA
     pub founder_authority: UncheckedAccount<'info>,
 
-    pub token_program: Program<'info, Token>,
-
     #[account(
         mut,
         constraint = program_up_usdc_account.mint == metadata.up_usdc_mint,
@@ -2000,42 +5949,31 @@ pub struct EarlyCloseLeverage<'info> {
         mut,
         constraint = up_usdc_mint.key() == metadata.up_usdc_mint
     )]
-    pub up_usdc_mint: Account<'info, Mint>,
+    pub up_usdc_mint: Box<Account<'info, Mint>>,
 
     #[account(
         seeds = [b"token_account", up_usdc_mint.key().as_ref()],
         bump
     )]
-    /// CHECK: PDA used as signer for burning from program_up_usdc_account
+    /// CHECK: signer for burning from program_up_usdc_account
     pub up_pool_authority: UncheckedAccount<'info>,
 
-    #[account(
-        mut,
-        constraint = referral_usdc_account.key() == anchor_lang::solana_program::system_program::ID || referral_usdc_account.mint == metadata.payment_token
-    )]
-    pub referral_usdc_account: Option<Account<'info, TokenAccount>>,
-}
-
-#[derive(Accounts)]
-pub struct ClaimLockedTokens<'info> {
-    pub cranker: Signer<'info>,
+    pub token_program: Program<'info, Token>,
 
-    #[account(
-        seeds = [b"token_account", metadata.payment_token.as_ref()],
-        bump
-    )]
-    /// CHECK: signer for transferring from program_payment_token_account
-    pub pool_authority: UncheckedAccount<'info>,
+    pub receipt_token_account: Option<Box<Account<'info, TokenAccount>>>,
 
-    #[account(
-        seeds = [b"metadata", token_mint.key().as_ref()],
-        bump
-    )]
-    pub metadata: Account<'info, TokenMetadata>,
+    #[account(seeds = [b"protocol_state"], bump)]
+    pub protocol_state: Box<Account<'info, ProtocolState>>,
+}
 
+#[derive(Accounts)]
+pub struct EarlyUnlockTokens<'info> {
+    /// Lock owner, or the current holder of the position receipt
     #[account(mut)]
-    ///CHECK: Used to derive vault PDA; must authorize claim/sell
-    pub user: Signer<'info>,
+    pub holder: Signer<'info>,
+
+    /// CHECK: Lock owner; used to derive the lock and vault PDAs
+    pub user: UncheckedAccount<'info>,
 
     #[account(
         mut,
@@ -2049,7 +5987,7 @@ pub struct ClaimLockedTokens<'info> {
         seeds = [b"vault", user.key().as_ref()],
         bump
     )]
-    /// CHECK: Only used as signer
+    /// CHECK: signer
     pub vault_authority: UncheckedAccount<'info>,
 
     #[account(
@@ -2064,26 +6002,46 @@ pub struct ClaimLockedTokens<'info> {
         mut,
         constraint = user_usdc_account.mint == metadata.payment_token,
         associated_token::mint = metadata.payment_token,
-        associated_token::authority = user
+        associated_token::authority = holder
     )]
-    pub user_usdc_account: Box<Account<'info, TokenAccount>>,
+    pub user_usdc_account: Account<'info, TokenAccount>,
 
-    #[account(
-        mut,
-        constraint = deployer_usdc_account.mint == metadata.payment_token,
-        constraint = deployer_usdc_account.key() == anchor_spl::associated_token::get_associated_token_address(&metadata.team, &metadata.payment_token)
-    )]
-    pub deployer_usdc_account: Box<Account<'info, TokenAccount>>,
+    #[account(mut, seeds = [b"team_treasury"], bump)]
+    pub team_treasury: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [b"fee_split"], bump)]
+    pub fee_split: Box<Account<'info, FeeSplit>>,
+
+    #[account(mut, seeds = [b"referral", referral_account.referrer.as_ref()], bump)]
+    pub referral_account: Option<Box<Account<'info, ReferralAccount>>>,
+
+    #[account(mut, seeds = [b"referral_pool"], bump)]
+    pub referral_pool: Option<Box<Account<'info, TokenAccount>>>,
 
     #[account(
         mut,
-        constraint = program_payment_token_account.mint == metadata.payment_token
+        constraint = program_payment_token_account.mint == metadata.payment_token,
+        constraint = program_payment_token_account.key()
+            == anchor_spl::associated_token::get_associated_token_address(
+                &pool_authority.key(),
+                &metadata.payment_token
+            )
     )]
-    pub program_payment_token_account: Box<Account<'info, TokenAccount>>,
+    pub program_payment_token_account: Account<'info, TokenAccount>,
 
     #[account(mut)]
     pub token_mint: Account<'info, Mint>,
 
+    #[account(mut)]
+    pub metadata: Account<'info, TokenMetadata>,
+
+    #[account(
+        seeds = [b"token_account", metadata.payment_token.as_ref()],
+        bump
+    )]
+    /// CHECK
+    pub pool_authority: UncheckedAccount<'info>,
+
     #[account(
         mut,
         constraint = founder_pool_token_account.mint == metadata.payment_token,
@@ -2108,7 +6066,7 @@ pub struct ClaimLockedTokens<'info> {
         constraint = program_up_usdc_account.mint == metadata.up_usdc_mint,
         constraint = program_up_usdc_account.key() == anchor_spl::associated_token::get_associated_token_address(&up_pool_authority.key(), &metadata.up_usdc_mint)
     )]
-    pub program_up_usdc_account: Box<Account<'info, TokenAccount>>,
+    pub program_up_usdc_account: Account<'info, TokenAccount>,
 
     #[account(
         mut,
@@ -2121,27 +6079,89 @@ pub struct ClaimLockedTokens<'info> {
         bump
     )]
     /// CHECK: signer for burning from program_up_usdc_account
-   pub up_pool_authority: UncheckedAccount<'info>,
+    pub up_pool_authority: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub position_mint: Option<Box<Account<'info, Mint>>>,
+
+    #[account(mut)]
+    pub receipt_token_account: Option<Box<Account<'info, TokenAccount>>>,
+
+    #[account(seeds = [b"protocol_state"], bump)]
+    pub protocol_state: Box<Account<'info, ProtocolState>>,
 }
 
 #[derive(Accounts)]
-pub struct EarlyUnlockTokens<'info> {
+#[instruction(new_founder: Pubkey)]
+pub struct AddFounder<'info> {
+    #[account(mut, has_one = deployer)]
+    pub metadata: Account<'info, TokenMetadata>,
+
+    #[account(mut, seeds = [b"founders_pool"], bump)]
+    pub founders_pool: Box<Account<'info, FoundersPool>>,
+
+    #[account(
+        init,
+        payer = deployer,
+        space = Founder::LEN,
+        seeds = [b"founder", new_founder.as_ref()],
+        bump
+    )]
+    pub founder_state: Box<Account<'info, Founder>>,
+
+    #[account(mut)]
+    pub deployer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AddFounders<'info> {
+    #[account(has_one = deployer)]
+    pub metadata: Account<'info, TokenMetadata>,
+
+    #[account(mut, seeds = [b"founders_pool"], bump)]
+    pub founders_pool: Box<Account<'info, FoundersPool>>,
+
+    #[account(mut)]
+    pub deployer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateFoundersPool<'info> {
+    #[account(has_one = deployer)]
+    pub metadata: Account<'info, TokenMetadata>,
+
+    /// CHECK: Legacy layout is decoded by hand in `migrate_founders_pool`
+    #[account(mut, owner = crate::ID, seeds = [b"founders_pool"], bump)]
+    pub founders_pool: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub deployer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CloseLockPosition<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
 
     #[account(
         mut,
+        close = user,
         seeds = [b"locked", user.key().as_ref()],
         bump
     )]
     pub lock_state: Account<'info, LockedTokenState>,
 
     #[account(
-        mut,
         seeds = [b"vault", user.key().as_ref()],
         bump
     )]
-    /// CHECK: signer
+    /// CHECK: Only used as signer
     pub vault_authority: UncheckedAccount<'info>,
 
     #[account(
@@ -2152,94 +6172,174 @@ pub struct EarlyUnlockTokens<'info> {
     )]
     pub vault_token_account: Account<'info, TokenAccount>,
 
+    pub token_mint: Account<'info, Mint>,
+
     #[account(
-        mut,
-        constraint = user_usdc_account.mint == metadata.payment_token,
-        associated_token::mint = metadata.payment_token,
-        associated_token::authority = user
+        seeds = [b"metadata", token_mint.key().as_ref()],
+        bump
     )]
-    pub user_usdc_account: Account<'info, TokenAccount>,
+    pub metadata: Account<'info, TokenMetadata>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(position_id: u64)]
+pub struct CloseLeveragePosition<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
 
     #[account(
         mut,
-        constraint = deployer_usdc_account.mint == metadata.payment_token,
-        constraint = deployer_usdc_account.key() == anchor_spl::associated_token::get_associated_token_address(&metadata.team, &metadata.payment_token)
+        close = user,
+        seeds = [b"leverage", user.key().as_ref(), &position_id.to_le_bytes()],
+        bump
+    )]
+    pub leverage_position: Account<'info, LeveragePosition>,
+
+    #[account(
+        seeds = [b"l_vault", user.key().as_ref(), &position_id.to_le_bytes()],
+        bump
     )]
-    pub deployer_usdc_account: Account<'info, TokenAccount>,
+    /// CHECK: Only used as signer
+    pub vault_authority: UncheckedAccount<'info>,
 
     #[account(
         mut,
-        constraint = program_payment_token_account.mint == metadata.payment_token,
-        constraint = program_payment_token_account.key()
-            == anchor_spl::associated_token::get_associated_token_address(
-                &pool_authority.key(),
-                &metadata.payment_token
-            )
+        associated_token::mint = token_mint,
+        associated_token::authority = vault_authority,
+        constraint = vault_token_account.mint == metadata.mint
     )]
-    pub program_payment_token_account: Account<'info, TokenAccount>,
+    pub vault_token_account: Account<'info, TokenAccount>,
 
-    #[account(mut)]
     pub token_mint: Account<'info, Mint>,
 
-    #[account(mut)]
+    #[account(
+        seeds = [b"metadata", token_mint.key().as_ref()],
+        bump
+    )]
     pub metadata: Account<'info, TokenMetadata>,
 
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateLockState<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// CHECK: Owner of the legacy lock, only used to derive its address
+    pub user: UncheckedAccount<'info>,
+
+    /// CHECK: Legacy layout is decoded by hand in `migrate_lock_state`
+    #[account(mut, owner = crate::ID, seeds = [b"locked", user.key().as_ref()], bump)]
+    pub lock_state: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(position_id: u64)]
+pub struct MigrateLegacyLeverage<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    /// CHECK: Legacy layout is decoded by hand in `migrate_legacy_leverage`
+    #[account(mut, owner = crate::ID, seeds = [b"leverage", user.key().as_ref()], bump)]
+    pub legacy_position: UncheckedAccount<'info>,
+
+    /// CHECK: Legacy vault PDA signer
+    #[account(seeds = [b"l_vault", user.key().as_ref()], bump)]
+    pub legacy_vault_authority: UncheckedAccount<'info>,
+
     #[account(
-        seeds = [b"token_account", metadata.payment_token.as_ref()],
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = legacy_vault_authority
+    )]
+    pub legacy_vault_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        init,
+        payer = user,
+        space = LeveragePosition::LEN,
+        seeds = [b"leverage", user.key().as_ref(), &position_id.to_le_bytes()],
         bump
     )]
-    /// CHECK
-    pub pool_authority: UncheckedAccount<'info>,
+    pub leverage_position: Box<Account<'info, LeveragePosition>>,
 
     #[account(
-        mut,
-        constraint = founder_pool_token_account.mint == metadata.payment_token,
-        constraint = founder_pool_token_account.key() == anchor_spl::associated_token::get_associated_token_address(&founder_authority.key(), &metadata.payment_token)
+        init_if_needed,
+        payer = user,
+        space = 8 + 32 + 8 + 4 + MAX_LEVERAGE_POSITIONS * 8,
+        seeds = [b"leverage_registry", user.key().as_ref()],
+        bump
     )]
-    pub founder_pool_token_account: Box<Account<'info, TokenAccount>>,
+    pub leverage_registry: Box<Account<'info, LeverageRegistry>>,
 
-    #[account(mut, seeds = [b"founders_pool"], bump)]
-    pub founders_pool: Box<Account<'info, FoundersPool>>,
+    /// CHECK: Vault PDA signer
+    #[account(seeds = [b"l_vault", user.key().as_ref(), &position_id.to_le_bytes()], bump)]
+    pub vault_authority: UncheckedAccount<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        associated_token::mint = token_mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, seeds = [b"protocol_state"], bump)]
+    pub protocol_state: Box<Account<'info, ProtocolState>>,
+
+    #[account(
+        constraint = token_mint.key() == metadata.mint
+    )]
+    pub token_mint: Box<Account<'info, Mint>>,
 
     #[account(
-        seeds = [b"founder_authority"],
+        seeds = [b"metadata", token_mint.key().as_ref()],
         bump
     )]
-    /// CHECK: PDA used as authority/owner of founder pool ATA
-    pub founder_authority: UncheckedAccount<'info>,
+    pub metadata: Box<Account<'info, TokenMetadata>>,
 
+    pub system_program: Program<'info, System>,
     pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct CloseLegacyLeverage<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    /// CHECK: Legacy layout is decoded by hand in `close_legacy_leverage`
+    #[account(mut, owner = crate::ID, seeds = [b"leverage", user.key().as_ref()], bump)]
+    pub legacy_position: UncheckedAccount<'info>,
+
+    /// CHECK: Legacy vault PDA signer
+    #[account(seeds = [b"l_vault", user.key().as_ref()], bump)]
+    pub legacy_vault_authority: UncheckedAccount<'info>,
 
     #[account(
         mut,
-        constraint = program_up_usdc_account.mint == metadata.up_usdc_mint,
-        constraint = program_up_usdc_account.key() == anchor_spl::associated_token::get_associated_token_address(&up_pool_authority.key(), &metadata.up_usdc_mint)
+        associated_token::mint = token_mint,
+        associated_token::authority = legacy_vault_authority
     )]
-    pub program_up_usdc_account: Account<'info, TokenAccount>,
+    pub legacy_vault_token_account: Box<Account<'info, TokenAccount>>,
 
     #[account(
-        mut,
-        constraint = up_usdc_mint.key() == metadata.up_usdc_mint
+        constraint = token_mint.key() == metadata.mint
     )]
-    pub up_usdc_mint: Account<'info, Mint>,
+    pub token_mint: Box<Account<'info, Mint>>,
 
     #[account(
-        seeds = [b"token_account", up_usdc_mint.key().as_ref()],
+        seeds = [b"metadata", token_mint.key().as_ref()],
         bump
     )]
-    /// CHECK: signer for burning from program_up_usdc_account
-    pub up_pool_authority: UncheckedAccount<'info>,
-}
-
-#[derive(Accounts)]
-pub struct AddFounder<'info> {
-    #[account(mut, has_one = deployer)]
-    pub metadata: Account<'info, TokenMetadata>,
-
-    #[account(mut, seeds = [b"founders_pool"], bump)]
-    pub founders_pool: Box<Account<'info, FoundersPool>>,
+    pub metadata: Box<Account<'info, TokenMetadata>>,
 
-    pub deployer: Signer<'info>,
+    pub token_program: Program<'info, Token>,
 }
 
 #[derive(Accounts)]
@@ -2247,8 +6347,11 @@ pub struct InitializeUserVault<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
 
+    /// CHECK: Owner of the lock the vault is created for; pass `user` for a self-owned lock
+    pub beneficiary: UncheckedAccount<'info>,
+
     /// CHECK: Only used as a derived signer authority
-    #[account(seeds = [b"vault", user.key().as_ref()], bump)]
+    #[account(seeds = [b"vault", beneficiary.key().as_ref()], bump)]
     pub vault_authority: UncheckedAccount<'info>,
 
     #[account(
@@ -2276,12 +6379,13 @@ pub struct InitializeUserVault<'info> {
 }
 
 #[derive(Accounts)]
+#[instruction(position_id: u64)]
 pub struct InitializeLeverageUserVault<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
 
     /// CHECK: Only used as a derived signer authority
-    #[account(seeds = [b"l_vault", user.key().as_ref()], bump)]
+    #[account(seeds = [b"l_vault", user.key().as_ref(), &position_id.to_le_bytes()], bump)]
     pub vault_authority: UncheckedAccount<'info>,
 
     #[account(
@@ -2313,6 +6417,125 @@ pub struct GlobalState {
     pub initialized: bool,
 }
 
+#[account]
+pub struct ProtocolState {
+    pub early_penalty_min_bps: u64,
+    pub early_penalty_max_bps: u64,
+    pub early_penalty_to_liquidity: bool,
+    pub min_lock_seconds: u64,
+    pub max_lock_seconds: u64,
+    pub fee_tiers: Vec<FeeTier>,
+    pub max_leverage_bps: u64,
+    pub total_borrowed: u64,
+    pub max_total_borrowed: u64,
+    pub max_borrow_utilization_bps: u64,
+    pub bad_debt: u64,
+    pub referral_tiers: Vec<ReferralTier>,
+}
+
+impl ProtocolState {
+    /// Removes `amount` from `total_borrowed`, flooring at zero. Legacy
+    /// positions only enter the total once `migrate_legacy_leverage` runs, so
+    /// an accounting gap must not block a position from closing.
+    pub fn release_borrow(&mut self, amount: u64) {
+        self.total_borrowed = self.total_borrowed.saturating_sub(amount);
+    }
+}
+
+/// Referrers with at least `min_volume` referred get `share_bps` of the team fee.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct ReferralTier {
+    pub min_volume: u64,
+    pub share_bps: u64,
+}
+
+/// Splits the team fee between recipients. Credited shares stay in the team
+/// treasury until claimed; `outstanding` is their unclaimed total.
+#[account]
+pub struct FeeSplit {
+    pub outstanding: u64,
+    pub recipients: Vec<FeeRecipient>,
+}
+
+impl FeeSplit {
+    pub fn credit(&mut self, team_fee: u64) -> Result<()> {
+        for entry in self.recipients.iter_mut() {
+            let share = bps_of(team_fee, entry.bps)?;
+            entry.accrued = entry
+                .accrued
+                .checked_add(share)
+                .ok_or(CustomError::MathOverflow)?;
+            self.outstanding = self
+                .outstanding
+                .checked_add(share)
+                .ok_or(CustomError::MathOverflow)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct FeeRecipient {
+    pub recipient: Pubkey,
+    pub bps: u64,
+    pub accrued: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct FeeRecipientSpec {
+    pub recipient: Pubkey,
+    pub bps: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum BadDebtSource {
+    InsuranceFund,
+    TeamFees,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct FeeTier {
+    pub lock_seconds: u64,
+    pub liquidity_bps: u64,
+    pub team_bps: u64,
+    pub founder_bps: u64,
+}
+
+impl FeeTier {
+    pub fn fee_config(&self) -> LockFeeConfig {
+        LockFeeConfig {
+            liquidity_bps: self.liquidity_bps,
+            team_bps: self.team_bps,
+            founder_bps: self.founder_bps,
+        }
+    }
+}
+
+/// Referral earnings of one referrer. Shares accrue here and are paid out of
+/// the referral pool by `claim_referral_rewards`.
+#[account]
+pub struct ReferralAccount {
+    pub referrer: Pubkey,
+    pub accrued: u64,
+    pub total_earned: u64,
+    pub total_claimed: u64,
+    pub referred_volume: u64,
+}
+
+/// Opt-in by `beneficiary` allowing `payer` to open locks in its name.
+#[account]
+pub struct LockPayerApproval {
+    pub beneficiary: Pubkey,
+    pub payer: Pubkey,
+}
+
+#[account]
+pub struct ReferralCode {
+    pub referrer: Pubkey,
+    pub code: String,
+}
+
 #[account]
 pub struct UserState {
     pub referral: Pubkey,
@@ -2339,7 +6562,46 @@ pub struct LockedTokenState {
     pub unlock_time: i64,
     pub referral: Option<Pubkey>,
     pub initialized: bool,
+    pub lock_seconds: u64,
+    pub auto_settle: bool,
+    pub auto_rollover: bool,
+    pub position_mint: Option<Pubkey>,
+    pub vesting: bool,
+    pub start_time: i64,
+    pub end_time: i64,
+    pub claimed_amount: u64,
+    pub version: u8,
+    /// Fee schedule in force when the lock was opened or last rolled over by
+    /// its owner. Exits are charged from this snapshot.
+    pub fee_config: LockFeeConfig,
+}
+
+impl LockedTokenState {
+    pub const LEN: usize = 8 + 32 + 8 + 8 + 33 + 1 + 8 + 1 + 1 + 33 + 1 + 8 + 8 + 8 + 1 + 24;
+}
+
+/// Pre-versioning layout of `LockedTokenState`, with the lock length in hours.
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct LegacyLockedTokenState {
+    pub user: Pubkey,
+    pub amount: u64,
+    pub unlock_time: i64,
+    pub referral: Option<Pubkey>,
+    pub initialized: bool,
+    pub lock_hour: u64,
+}
+
+/// Single-slot layout of `LeveragePosition`, with the lock length in hours.
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct LegacyLeveragePosition {
+    pub user: Pubkey,
+    pub amount_user_paid: u64,
+    pub amount_borrowed: u64,
+    pub unlock_time: i64,
+    pub referral: Option<Pubkey>,
+    pub initialized: bool,
     pub lock_hour: u64,
+    pub amount_minted: u64,
 }
 
 #[account]
@@ -2350,17 +6612,151 @@ pub struct LeveragePosition {
     pub unlock_time: i64,
     pub referral: Option<Pubkey>,
     pub initialized: bool,
-    pub lock_hour: u64,
+    pub lock_seconds: u64,
     pub amount_minted: u64,
+    pub leverage_bps: u64,
+    pub take_profit_price: Option<u64>,
+    pub stop_loss_price: Option<u64>,
+    /// Fee schedule in force when the position was opened.
+    pub fee_config: LockFeeConfig,
+    /// Slot the take-profit / stop-loss prices were last set in.
+    pub order_set_slot: u64,
+    /// Spot price recorded by `observe_price` and the slot it was taken in.
+    pub observed_price: u64,
+    pub observed_slot: u64,
+}
+
+impl LeveragePosition {
+    pub const LEN: usize = 8 + 32 + 8 + 8 + 8 + 33 + 1 + 8 + 8 + 8 + 9 + 9 + 24 + 8 + 8 + 8;
+
+    pub fn order_triggered(&self, price: u64) -> bool {
+        self.take_profit_price.is_some_and(|tp| price >= tp)
+            || self.stop_loss_price.is_some_and(|sl| price <= sl)
+    }
+}
+
+/// Per-user index of leverage position ids. Ids are handed out sequentially
+/// from `next_id` and never reused.
+#[account]
+pub struct LeverageRegistry {
+    pub user: Pubkey,
+    pub next_id: u64,
+    pub open_ids: Vec<u64>,
+}
+
+impl LeverageRegistry {
+    pub fn remove(&mut self, position_id: u64) {
+        self.open_ids.retain(|&id| id != position_id);
+    }
 }
+
+/// Founder revenue is shared through an accumulator: `acc_per_share` grows by
+/// every credited fee divided by `total_shares`, and each founder is entitled
+/// to the growth since they joined, scaled by their shares.
 #[account]
 pub struct FoundersPool {
+    pub total_collected: u64,
+    pub founder_count: u64,
+    pub total_shares: u64,
+    pub acc_per_share: u128,
+    /// Fees collected while there were no founders to credit.
+    pub undistributed: u64,
+}
+
+impl FoundersPool {
+    pub const LEN: usize = 8 + 8 + 8 + 8 + 16 + 8;
+
+    pub fn credit(&mut self, amount: u64) -> Result<()> {
+        self.total_collected = self
+            .total_collected
+            .checked_add(amount)
+            .ok_or(CustomError::MathOverflow)?;
+
+        if self.total_shares == 0 {
+            self.undistributed = self
+                .undistributed
+                .checked_add(amount)
+                .ok_or(CustomError::MathOverflow)?;
+            return Ok(());
+        }
+
+        let pending = (amount as u128)
+            .checked_add(self.undistributed as u128)
+            .and_then(|v| v.checked_mul(ACC_PRECISION))
+            .ok_or(CustomError::MathOverflow)?;
+        self.acc_per_share = self
+            .acc_per_share
+            .checked_add(pending / self.total_shares as u128)
+            .ok_or(CustomError::MathOverflow)?;
+        self.undistributed = 0;
+
+        Ok(())
+    }
+
+    /// Adds `founder_state` to the pool. Its entitlement starts at the current
+    /// accumulator, so past fees are not shared with new founders.
+    pub fn join(&mut self, founder_state: &mut Founder) -> Result<()> {
+        require!(founder_state.shares > 0, CustomError::InvalidFounderShares);
+
+        founder_state.acc_per_share_start = self.acc_per_share;
+        self.total_shares = self
+            .total_shares
+            .checked_add(founder_state.shares)
+            .ok_or(CustomError::MathOverflow)?;
+        self.founder_count = self
+            .founder_count
+            .checked_add(1)
+            .ok_or(CustomError::MathOverflow)?;
+
+        Ok(())
+    }
+
+    pub fn entitlement(&self, founder_state: &Founder) -> Result<u64> {
+        let owed = self
+            .acc_per_share
+            .checked_sub(founder_state.acc_per_share_start)
+            .and_then(|v| v.checked_mul(founder_state.shares as u128))
+            .ok_or(CustomError::MathOverflow)?
+            / ACC_PRECISION;
+        u64::try_from(owed).map_err(|_| CustomError::MathOverflow.into())
+    }
+}
+
+/// Pre-PDA layout of `FoundersPool`: 60 fixed slots with the amount each
+/// founder has claimed.
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct LegacyFoundersPool {
     pub total_collected: u64,
     pub founders: Vec<Pubkey>,
     pub claim_status: Vec<u64>,
     pub founder_count: u8,
 }
 
+/// One entry of `add_founders`. Unset fields default to a single share and
+/// no vesting; a cliff without `vesting_seconds` vests fully at the cliff.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct FounderSpec {
+    pub founder: Pubkey,
+    pub shares: Option<u64>,
+    pub cliff_seconds: Option<u64>,
+    pub vesting_seconds: Option<u64>,
+}
+
+#[account]
+pub struct Founder {
+    pub founder: Pubkey,
+    pub shares: u64,
+    pub acc_per_share_start: u128,
+    pub claimed: u64,
+    pub vesting_start: i64,
+    pub cliff_seconds: u64,
+    pub vesting_seconds: u64,
+}
+
+impl Founder {
+    pub const LEN: usize = 8 + 32 + 8 + 16 + 8 + 8 + 8 + 8;
+}
+
 #[account]
 pub struct LockFeeConfig {
     pub liquidity_bps: u64,
@@ -2435,4 +6831,478 @@ pub enum CustomError {
 
     #[msg("Invalid program upUSDC account owner")]
     InvalidProgramUpUsdcAccount,
+
+    #[msg("Arithmetic overflow")]
+    MathOverflow,
+
+    #[msg("Arithmetic underflow")]
+    MathUnderflow,
+
+    #[msg("Position must be claimed or closed first")]
+    PositionStillOpen,
+
+    #[msg("Vault token account still holds tokens")]
+    VaultNotEmpty,
+
+    #[msg("Position has not opted in to auto settlement")]
+    AutoSettleDisabled,
+
+    #[msg("Auto settle and auto rollover cannot both be enabled")]
+    ConflictingLockOptions,
+
+    #[msg("Keeper token account must be provided")]
+    MissingKeeperAccount,
+
+    #[msg("Position receipt accounts must be provided")]
+    MissingReceiptAccount,
+
+    #[msg("Position receipt does not match the lock or holder")]
+    InvalidReceipt,
+
+    #[msg("Not supported for positions with a receipt")]
+    ReceiptPositionUnsupported,
+
+    #[msg("Lock is not a vesting lock")]
+    NotVestingLock,
+
+    #[msg("Invalid early unlock penalty configuration")]
+    InvalidPenaltyConfig,
+
+    #[msg("Invalid lock fee tiers")]
+    InvalidFeeTiers,
+
+    #[msg("Global borrow cap exceeded")]
+    BorrowCapExceeded,
+
+    #[msg("Invalid borrow caps")]
+    InvalidBorrowCaps,
+
+    #[msg("Take-profit must be above stop-loss")]
+    InvalidOrderPrices,
+
+    #[msg("Order price has not been reached")]
+    OrderNotTriggered,
+
+    #[msg("Position id must be the next id in the registry")]
+    InvalidPositionId,
+
+    #[msg("Too many open leverage positions")]
+    TooManyPositions,
+
+    #[msg("Invalid bad debt source account")]
+    InvalidBadDebtSource,
+
+    #[msg("Cliff cannot be longer than the vesting period")]
+    InvalidVestingSchedule,
+
+    #[msg("Founder shares must be greater than zero")]
+    InvalidFounderShares,
+
+    #[msg("Remaining accounts must be founder state and token account pairs")]
+    InvalidRemainingAccounts,
+
+    #[msg("Invalid fee split")]
+    InvalidFeeSplit,
+
+    #[msg("Fee recipient still has an unclaimed balance")]
+    FeeRecipientHasBalance,
+
+    #[msg("Not a fee split recipient")]
+    NotFeeRecipient,
+
+    #[msg("Invalid referral tiers")]
+    InvalidReferralTiers,
+
+    #[msg("Referral code must be 3-32 characters of a-z, 0-9, _ or -")]
+    InvalidReferralCode,
+
+    #[msg("Account already uses the current layout")]
+    AlreadyMigrated,
+
+    #[msg("Beneficiary has not approved this payer")]
+    BeneficiaryNotApproved,
+
+    #[msg("Price must be observed in an earlier, recent slot")]
+    StalePriceObservation,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vested_amount_is_linear_between_start_and_end() {
+        assert_eq!(vested_amount(1_000, 100, 200, 50).unwrap(), 0);
+        assert_eq!(vested_amount(1_000, 100, 200, 100).unwrap(), 0);
+        assert_eq!(vested_amount(1_000, 100, 200, 125).unwrap(), 250);
+        assert_eq!(vested_amount(1_000, 100, 200, 199).unwrap(), 990);
+        assert_eq!(vested_amount(1_000, 100, 200, 200).unwrap(), 1_000);
+        assert_eq!(vested_amount(1_000, 100, 200, 300).unwrap(), 1_000);
+    }
+
+    #[test]
+    fn vested_amount_rounds_down_and_handles_empty_schedule() {
+        assert_eq!(vested_amount(10, 0, 3, 1).unwrap(), 3);
+        assert_eq!(vested_amount(1_000, 100, 100, 50).unwrap(), 1_000);
+        assert_eq!(vested_amount(u64::MAX, 0, 2, 1).unwrap(), u64::MAX / 2);
+    }
+
+    fn penalty_state(min_bps: u64, max_bps: u64) -> ProtocolState {
+        ProtocolState {
+            early_penalty_min_bps: min_bps,
+            early_penalty_max_bps: max_bps,
+            early_penalty_to_liquidity: true,
+            min_lock_seconds: 3_600,
+            max_lock_seconds: 30 * 24 * 3_600,
+            fee_tiers: default_fee_tiers(),
+            max_leverage_bps: 50_000,
+            total_borrowed: 0,
+            max_total_borrowed: 0,
+            max_borrow_utilization_bps: 0,
+            bad_debt: 0,
+            referral_tiers: vec![],
+        }
+    }
+
+    fn leverage_state(total_borrowed: u64, cap_bps: u64) -> ProtocolState {
+        ProtocolState {
+            total_borrowed,
+            max_borrow_utilization_bps: cap_bps,
+            ..penalty_state(0, 0)
+        }
+    }
+
+    #[test]
+    fn max_leverage_steps_down_with_utilization() {
+        assert_eq!(effective_max_leverage_bps(&leverage_state(0, 8_000), 1_000).unwrap(), 50_000);
+        // 40% of an 80% cap used leaves half the headroom.
+        assert_eq!(effective_max_leverage_bps(&leverage_state(400, 8_000), 1_000).unwrap(), 30_000);
+        // 60% of 80% leaves a quarter.
+        assert_eq!(effective_max_leverage_bps(&leverage_state(600, 8_000), 1_000).unwrap(), 20_000);
+    }
+
+    #[test]
+    fn max_leverage_is_unlevered_at_or_past_the_cap() {
+        assert_eq!(effective_max_leverage_bps(&leverage_state(800, 8_000), 1_000).unwrap(), 10_000);
+        assert_eq!(effective_max_leverage_bps(&leverage_state(900, 8_000), 1_000).unwrap(), 10_000);
+        assert_eq!(effective_max_leverage_bps(&leverage_state(1, 8_000), 0).unwrap(), 10_000);
+        assert_eq!(effective_max_leverage_bps(&leverage_state(0, 0), 1_000).unwrap(), 10_000);
+    }
+
+    #[test]
+    fn max_leverage_just_below_the_cap_rounds_down() {
+        assert_eq!(effective_max_leverage_bps(&leverage_state(799, 8_000), 1_000).unwrap(), 10_050);
+        let mut state = leverage_state(0, 8_000);
+        state.max_leverage_bps = 10_000;
+        assert_eq!(effective_max_leverage_bps(&state, 1_000).unwrap(), 10_000);
+    }
+
+    #[test]
+    fn early_penalty_scales_with_remaining_time() {
+        let state = penalty_state(100, 1_100);
+        let mut config = LockFeeConfig { liquidity_bps: 500, team_bps: 300, founder_bps: 25 };
+        apply_early_penalty(&mut config, &state, 1_500, 1_000, 1_000).unwrap();
+        assert_eq!(config.liquidity_bps, 500 + 100 + 500);
+        assert_eq!(config.team_bps, 300);
+    }
+
+    #[test]
+    fn early_penalty_is_clamped_below_full_amount() {
+        let state = penalty_state(9_000, 9_000);
+        let mut config = LockFeeConfig { liquidity_bps: 900, team_bps: 300, founder_bps: 25 };
+        apply_early_penalty(&mut config, &state, 2_000, 1_000, 1_000).unwrap();
+        let total = config.liquidity_bps + config.team_bps + config.founder_bps;
+        assert_eq!(total, MAX_EXIT_FEE_BPS);
+    }
+
+    #[test]
+    fn interpolate_bps_moves_linearly_in_both_directions() {
+        assert_eq!(interpolate_bps(100, 200, 0, 10).unwrap(), 100);
+        assert_eq!(interpolate_bps(100, 200, 5, 10).unwrap(), 150);
+        assert_eq!(interpolate_bps(100, 200, 10, 10).unwrap(), 200);
+        assert_eq!(interpolate_bps(200, 100, 3, 10).unwrap(), 170);
+        assert_eq!(interpolate_bps(100, 101, 1, 3).unwrap(), 100);
+        assert_eq!(interpolate_bps(100, 200, 0, 0).unwrap(), 200);
+    }
+
+    #[test]
+    fn lock_fee_config_interpolates_between_tiers_and_clamps() {
+        let tiers = default_fee_tiers();
+        let below = get_lock_fee_config(60, &tiers).unwrap();
+        assert_eq!((below.liquidity_bps, below.team_bps), (150, 75));
+        let between = get_lock_fee_config(5 * 3_600, &tiers).unwrap();
+        assert_eq!((between.liquidity_bps, between.team_bps), (412, 162));
+        let above = get_lock_fee_config(30 * 24 * 3_600, &tiers).unwrap();
+        assert_eq!((above.liquidity_bps, above.team_bps), (925, 300));
+    }
+
+    #[test]
+    fn quote_exit_prices_amount_against_liquidity() {
+        let config = LockFeeConfig { liquidity_bps: 100, team_bps: 50, founder_bps: 50 };
+        // 2_000 USDC backing 1_000 tokens, selling 10 tokens.
+        let quote = quote_exit(2_000_000_000, 1_000_000_000_000, 10_000_000_000, &config).unwrap();
+        assert_eq!(quote.total_value, 20_000_000);
+        assert_eq!(quote.founder_fee, 100_000);
+        assert_eq!(quote.team_fee, 100_000);
+        assert_eq!(quote.liquidity_fee, 200_000);
+        assert_eq!(quote.after_fees().unwrap(), 19_600_000);
+    }
+
+    #[test]
+    fn quote_exit_fees_never_exceed_total_value_at_fee_cap() {
+        let config = LockFeeConfig { liquidity_bps: 3_333, team_bps: 3_333, founder_bps: 3_333 };
+        for total in [1u64, 7, 999, 10_001, 123_457] {
+            let quote = quote_exit(total, 1_000_000_000, 1_000_000_000, &config).unwrap();
+            assert_eq!(quote.total_value, total);
+            let fees = quote.founder_fee + quote.team_fee + quote.liquidity_fee;
+            assert_eq!(fees, bps_of(total, MAX_EXIT_FEE_BPS).unwrap());
+            assert!(quote.after_fees().is_ok());
+        }
+    }
+
+    fn exit_quote(total_value: u64) -> ExitQuote {
+        ExitQuote { total_value, founder_fee: 10, team_fee: 30, liquidity_fee: 60 }
+    }
+
+    #[test]
+    fn settle_leverage_repays_loan_and_pays_fees_when_covered() {
+        let settlement = settle_leverage(&exit_quote(1_000), 600).unwrap();
+        assert_eq!(settlement.founder_fee, 10);
+        assert_eq!(settlement.team_fee, 30);
+        assert_eq!(settlement.user_cut, 300);
+        assert_eq!(settlement.repaid, 600);
+        assert_eq!(settlement.bad_debt, 0);
+    }
+
+    #[test]
+    fn settle_leverage_waives_fees_before_recording_bad_debt() {
+        let settlement = settle_leverage(&exit_quote(1_000), 950).unwrap();
+        assert_eq!((settlement.founder_fee, settlement.team_fee), (0, 0));
+        assert_eq!(settlement.user_cut, 0);
+        assert_eq!(settlement.repaid, 950);
+        assert_eq!(settlement.bad_debt, 0);
+
+        let underwater = settle_leverage(&exit_quote(1_000), 1_400).unwrap();
+        assert_eq!(underwater.user_cut, 0);
+        assert_eq!(underwater.repaid, 1_000);
+        assert_eq!(underwater.bad_debt, 400);
+    }
+
+    fn founder(shares: u64) -> Founder {
+        Founder {
+            founder: Pubkey::new_unique(),
+            shares,
+            acc_per_share_start: 0,
+            claimed: 0,
+            vesting_start: 0,
+            cliff_seconds: 0,
+            vesting_seconds: 0,
+        }
+    }
+
+    fn empty_founders_pool() -> FoundersPool {
+        FoundersPool {
+            total_collected: 0,
+            founder_count: 0,
+            total_shares: 0,
+            acc_per_share: 0,
+            undistributed: 0,
+        }
+    }
+
+    #[test]
+    fn founders_pool_does_not_dilute_fees_collected_before_join() {
+        let mut pool = empty_founders_pool();
+        let mut early = founder(1);
+        pool.join(&mut early).unwrap();
+        pool.credit(600).unwrap();
+
+        let mut late = founder(2);
+        pool.join(&mut late).unwrap();
+        pool.credit(600).unwrap();
+
+        assert_eq!(pool.total_collected, 1_200);
+        assert_eq!(pool.entitlement(&early).unwrap(), 800);
+        assert_eq!(pool.entitlement(&late).unwrap(), 400);
+    }
+
+    #[test]
+    fn founders_pool_holds_fees_until_the_first_founder() {
+        let mut pool = empty_founders_pool();
+        pool.credit(500).unwrap();
+        assert_eq!(pool.undistributed, 500);
+
+        let mut first = founder(1);
+        pool.join(&mut first).unwrap();
+        pool.credit(100).unwrap();
+        assert_eq!(pool.undistributed, 0);
+        assert_eq!(pool.entitlement(&first).unwrap(), 600);
+    }
+
+    #[test]
+    fn founders_pool_accepts_more_than_sixty_founders() {
+        let mut pool = empty_founders_pool();
+        let mut founders: Vec<Founder> = (0..100).map(|_| founder(1)).collect();
+        for founder_state in founders.iter_mut() {
+            pool.join(founder_state).unwrap();
+        }
+        assert!(pool.join(&mut founder(0)).is_err());
+        assert_eq!(pool.founder_count, 100);
+        assert_eq!(pool.total_shares, 100);
+
+        pool.credit(10_000).unwrap();
+        for founder_state in &founders {
+            assert_eq!(pool.entitlement(founder_state).unwrap(), 100);
+        }
+    }
+
+    fn fee_recipient(bps: u64) -> FeeRecipientSpec {
+        FeeRecipientSpec { recipient: Pubkey::new_unique(), bps }
+    }
+
+    #[test]
+    fn fee_split_credit_floors_each_share() {
+        let mut split = FeeSplit {
+            outstanding: 0,
+            recipients: [3_333, 3_333, 3_334]
+                .iter()
+                .map(|&bps| FeeRecipient { recipient: Pubkey::new_unique(), bps, accrued: 0 })
+                .collect(),
+        };
+        split.credit(101).unwrap();
+        let accrued: Vec<u64> = split.recipients.iter().map(|r| r.accrued).collect();
+        assert_eq!(accrued, vec![33, 33, 33]);
+        assert_eq!(split.outstanding, 99);
+
+        split.credit(7).unwrap();
+        let accrued: Vec<u64> = split.recipients.iter().map(|r| r.accrued).collect();
+        assert_eq!(accrued, vec![35, 35, 35]);
+        assert_eq!(split.outstanding, 105);
+    }
+
+    #[test]
+    fn fee_split_must_sum_to_full_share() {
+        validate_fee_split(&[]).unwrap();
+        validate_fee_split(&[fee_recipient(10_000)]).unwrap();
+        validate_fee_split(&[fee_recipient(6_000), fee_recipient(4_000)]).unwrap();
+
+        assert!(validate_fee_split(&[fee_recipient(6_000), fee_recipient(3_999)]).is_err());
+        assert!(validate_fee_split(&[fee_recipient(6_000), fee_recipient(4_001)]).is_err());
+        assert!(validate_fee_split(&[fee_recipient(0)]).is_err());
+    }
+
+    #[test]
+    fn fee_split_rejects_duplicate_and_excess_recipients() {
+        let duplicate = fee_recipient(5_000);
+        assert!(validate_fee_split(&[duplicate.clone(), duplicate]).is_err());
+
+        let too_many: Vec<FeeRecipientSpec> = (0..=MAX_FEE_RECIPIENTS)
+            .map(|i| fee_recipient(if i == 0 { 10_000 } else { 0 }))
+            .collect();
+        assert!(validate_fee_split(&too_many).is_err());
+    }
+
+    #[test]
+    fn referral_code_accepts_lowercase_digits_and_separators() {
+        validate_referral_code("abc").unwrap();
+        validate_referral_code("up-only_2024").unwrap();
+        validate_referral_code(&"a".repeat(MAX_REFERRAL_CODE_LEN)).unwrap();
+    }
+
+    #[test]
+    fn referral_code_rejects_bad_lengths_and_characters() {
+        assert!(validate_referral_code("").is_err());
+        assert!(validate_referral_code(&"a".repeat(MIN_REFERRAL_CODE_LEN - 1)).is_err());
+        assert!(validate_referral_code(&"a".repeat(MAX_REFERRAL_CODE_LEN + 1)).is_err());
+        assert!(validate_referral_code("Upper").is_err());
+        assert!(validate_referral_code("with space").is_err());
+        assert!(validate_referral_code("dot.code").is_err());
+        assert!(validate_referral_code("café").is_err());
+    }
+
+    #[test]
+    fn resolve_referral_maps_code_to_registered_referrer() {
+        let referrer = Pubkey::new_unique();
+        let code_account = ReferralCode { referrer, code: "friend".to_string() };
+
+        assert_eq!(
+            resolve_referral(None, Some("friend"), Some(&code_account)).unwrap(),
+            Some(referrer)
+        );
+        assert_eq!(
+            resolve_referral(Some(referrer), Some("friend"), Some(&code_account)).unwrap(),
+            Some(referrer)
+        );
+        assert_eq!(resolve_referral(Some(referrer), None, None).unwrap(), Some(referrer));
+        assert_eq!(resolve_referral(None, None, None).unwrap(), None);
+    }
+
+    #[test]
+    fn resolve_referral_rejects_mismatched_code_or_referrer() {
+        let code_account = ReferralCode { referrer: Pubkey::new_unique(), code: "friend".to_string() };
+
+        assert!(resolve_referral(None, Some("friend"), None).is_err());
+        assert!(resolve_referral(None, Some("other"), Some(&code_account)).is_err());
+        assert!(
+            resolve_referral(Some(Pubkey::new_unique()), Some("friend"), Some(&code_account))
+                .is_err()
+        );
+    }
+
+    fn referral_tiers() -> Vec<ReferralTier> {
+        vec![
+            ReferralTier { min_volume: 0, share_bps: 2_000 },
+            ReferralTier { min_volume: 1_000, share_bps: 3_000 },
+            ReferralTier { min_volume: 5_000, share_bps: 5_000 },
+        ]
+    }
+
+    #[test]
+    fn referral_share_bps_picks_highest_qualifying_tier() {
+        let tiers = referral_tiers();
+        assert_eq!(referral_share_bps(0, &tiers), 2_000);
+        assert_eq!(referral_share_bps(999, &tiers), 2_000);
+        assert_eq!(referral_share_bps(1_000, &tiers), 3_000);
+        assert_eq!(referral_share_bps(u64::MAX, &tiers), 5_000);
+        assert_eq!(referral_share_bps(10, &tiers[1..]), 0);
+    }
+
+    #[test]
+    fn accrue_referral_counts_volume_before_the_next_tier() {
+        let tiers = referral_tiers();
+        let referrer = Pubkey::new_unique();
+        let mut account = ReferralAccount {
+            referrer,
+            accrued: 0,
+            total_earned: 0,
+            total_claimed: 0,
+            referred_volume: 0,
+        };
+
+        let share = accrue_referral(Some(referrer), Some(&mut account), &tiers, 100, 1_000).unwrap();
+        assert_eq!(share, 20);
+        let share = accrue_referral(Some(referrer), Some(&mut account), &tiers, 100, 0).unwrap();
+        assert_eq!(share, 30);
+        assert_eq!(account.accrued, 50);
+        assert_eq!(account.referred_volume, 1_000);
+    }
+
+    #[test]
+    fn accrue_referral_requires_account_for_referred_positions() {
+        let tiers = referral_tiers();
+        assert_eq!(accrue_referral(None, None, &tiers, 100, 1_000).unwrap(), 0);
+        assert!(accrue_referral(Some(Pubkey::new_unique()), None, &tiers, 100, 1_000).is_err());
+
+        let mut other = ReferralAccount {
+            referrer: Pubkey::new_unique(),
+            accrued: 0,
+            total_earned: 0,
+            total_claimed: 0,
+            referred_volume: 0,
+        };
+        assert!(
+            accrue_referral(Some(Pubkey::new_unique()), Some(&mut other), &tiers, 100, 1_000)
+                .is_err()
+        );
+    }
 }
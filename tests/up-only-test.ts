@@ -42,6 +42,33 @@ describe('UP ONLY TESTS', () => {
   let referralUsdcAccount: PublicKey;
   let secondUserUsdcAccount: PublicKey;
 
+  // Referral shares accrue in the referrer's ReferralAccount and are paid
+  // out of the referral pool by claimReferralRewards.
+  const [referralAccountPda] = PublicKey.findProgramAddressSync(
+    [Buffer.from('referral'), referral.publicKey.toBuffer()],
+    program.programId
+  );
+  const [referralPoolPda] = PublicKey.findProgramAddressSync(
+    [Buffer.from('referral_pool')],
+    program.programId
+  );
+  const referralAccrued = async () =>
+    Number((await program.account.referralAccount.fetch(referralAccountPda)).accrued);
+
+  // Fee accounts every buy and exit pays into.
+  const [teamTreasuryPda] = PublicKey.findProgramAddressSync(
+    [Buffer.from('team_treasury')],
+    program.programId
+  );
+  const [feeSplitPda] = PublicKey.findProgramAddressSync(
+    [Buffer.from('fee_split')],
+    program.programId
+  );
+  const [protocolStatePda] = PublicKey.findProgramAddressSync(
+    [Buffer.from('protocol_state')],
+    program.programId
+  );
+
   it('Creates a dummy USDC token and mints 1 million tokens', async () => {
     // Create a new mint
     mintAuthority = Keypair.generate();
@@ -527,7 +554,8 @@ describe('UP ONLY TESTS', () => {
         userState: userStatePda,
         userUsdcAccount: buyerUsdcAccount,
         deployerUsdcAccount: deployerUsdcAccount.address,
-        referralUsdcAccount: null,
+        referralAccount: null,
+        referralPool: null,
         metadata: metadataPda,
         upOnlyMint: upOnlyMint,
         tokenProgram: TOKEN_PROGRAM_ID,
@@ -552,6 +580,35 @@ describe('UP ONLY TESTS', () => {
     );
   });
 
+  it('Initializes the referral pool and registers the referrer', async () => {
+    await program.methods
+      .initializeReferralPool()
+      .accounts({
+        referralPool: referralPoolPda,
+        usdcMint: usdcMint,
+        metadata: metadataPda,
+        tokenMint: upOnlyMint,
+        authority: wallet.publicKey,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
+    await program.methods
+      .registerReferrer()
+      .accounts({
+        referrer: referral.publicKey,
+        referralAccount: referralAccountPda,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([referral])
+      .rpc();
+
+    const referralAccount = await program.account.referralAccount.fetch(referralAccountPda);
+    assert.equal(referralAccount.referrer.toBase58(), referral.publicKey.toBase58());
+    assert.equal(Number(referralAccount.accrued), 0, 'Nothing should be accrued yet');
+  });
+
   it('User buys a pass with referral for 10,000 USDC', async () => {
     const [userStatePda] = PublicKey.findProgramAddressSync(
       [Buffer.from('user_state'), secondUser.publicKey.toBuffer()],
@@ -565,21 +622,14 @@ describe('UP ONLY TESTS', () => {
       wallet.publicKey
     );
 
-    const referralUsdcAccount = await getOrCreateAssociatedTokenAccount(
-      connection,
-      wallet.payer,
-      usdcMint,
-      referral.publicKey
-    );
-
     const buyerStart = Number((await getAccount(connection, secondUserUsdcAccount)).amount);
     const deployerStart = Number((await getAccount(connection, deployerUsdcAccount.address)).amount);
-    const referralStart = Number((await getAccount(connection, referralUsdcAccount.address)).amount);
+    const referralStart = await referralAccrued();
     
     console.log('💰 Initial Balances:');
     console.log('👤 Buyer USDC:', buyerStart / 1e6);
     console.log('💼 Deployer USDC:', deployerStart / 1e6);
-    console.log('🤝 Referral USDC:', referralStart / 1e6);
+    console.log('🤝 Referral accrued:', referralStart / 1e6);
 
     // Execute buy_pass with referral
     await program.methods
@@ -589,7 +639,8 @@ describe('UP ONLY TESTS', () => {
         userState: userStatePda,
         userUsdcAccount: secondUserUsdcAccount,
         deployerUsdcAccount: deployerUsdcAccount.address,
-        referralUsdcAccount: referralUsdcAccount.address,
+        referralAccount: referralAccountPda,
+        referralPool: referralPoolPda,
         metadata: metadataPda,
         upOnlyMint: upOnlyMint,
         tokenProgram: TOKEN_PROGRAM_ID,
@@ -604,12 +655,12 @@ describe('UP ONLY TESTS', () => {
 
     const buyerEnd = Number((await getAccount(connection, secondUserUsdcAccount)).amount);
     const deployerEnd = Number((await getAccount(connection, deployerUsdcAccount.address)).amount);
-    const referralEnd = Number((await getAccount(connection, referralUsdcAccount.address)).amount);
+    const referralEnd = await referralAccrued();
 
     console.log('\n💰 Final Balances:');
     console.log('👤 Buyer USDC:', buyerEnd / 1e6);
     console.log('💼 Deployer USDC:', deployerEnd / 1e6);
-    console.log('🤝 Referral USDC:', referralEnd / 1e6);
+    console.log('🤝 Referral accrued:', referralEnd / 1e6);
 
     console.log('\n💸 Transaction Breakdown:');
     console.log('👤 Buyer spent:', (buyerStart - buyerEnd) / 1e6, 'USDC');
@@ -726,7 +777,8 @@ describe('UP ONLY TESTS', () => {
         metadata: metadataPda,
         tokenMint: upOnlyMint,
         mintAuthority: mintAuthorityPda,
-        referralUsdcAccount: null,
+        referralAccount: null,
+        referralPool: null,
         founderPoolTokenAccount: founderPoolTokenAccount.address,
         foundersPool: foundersPoolPda,
         tokenProgram: TOKEN_PROGRAM_ID,
@@ -839,13 +891,13 @@ describe('UP ONLY TESTS', () => {
 
     // Get initial balances
     const secondUserStart = Number((await getAccount(connection, secondUserUsdcAccount)).amount);
-    const referralStart = Number((await getAccount(connection, referralUsdcAccount)).amount);
+    const referralStart = await referralAccrued();
     const deployerStart = Number((await getAccount(connection, deployerUsdcAccount.address)).amount);
     const poolStart = Number((await getAccount(connection, programUsdcAccount.address)).amount);
 
     console.log('\n💰 Initial Balances:');
     console.log('👤 Second user USDC:', secondUserStart / 1e6);
-    console.log('🤝 Referral USDC:', referralStart / 1e6);
+    console.log('🤝 Referral accrued:', referralStart / 1e6);
     console.log('💼 Deployer USDC:', deployerStart / 1e6);
     console.log('🏦 Pool USDC:', poolStart / 1e6);
 
@@ -884,7 +936,8 @@ describe('UP ONLY TESTS', () => {
         metadata: metadataPda,
         tokenMint: upOnlyMint,
         poolAuthority: programUsdcAccount.address,
-        referralUsdcAccount: referralUsdcAccount,
+        referralAccount: referralAccountPda,
+        referralPool: referralPoolPda,
         tokenProgram: TOKEN_PROGRAM_ID,
         foundersPool: foundersPoolPda,
         founderPoolTokenAccount: founderPoolTokenAccount.address,
@@ -894,7 +947,7 @@ describe('UP ONLY TESTS', () => {
 
     // Get final balances
     const secondUserEnd = Number((await getAccount(connection, secondUserUsdcAccount)).amount);
-    const referralEnd = Number((await getAccount(connection, referralUsdcAccount)).amount);
+    const referralEnd = await referralAccrued();
     const deployerEnd = Number((await getAccount(connection, deployerUsdcAccount.address)).amount);
     const poolEnd = Number((await getAccount(connection, programUsdcAccount.address)).amount);
 
@@ -911,7 +964,7 @@ describe('UP ONLY TESTS', () => {
 
     console.log('\n💰 Final Balances:');
     console.log('👤 Second user USDC:', secondUserEnd / 1e6);
-    console.log('🤝 Referral USDC:', referralEnd / 1e6);
+    console.log('🤝 Referral accrued:', referralEnd / 1e6);
     console.log('💼 Deployer USDC:', deployerEnd / 1e6);
     console.log('🏦 Pool USDC:', poolEnd / 1e6);
 
@@ -1008,13 +1061,13 @@ describe('UP ONLY TESTS', () => {
 
     // Get initial balances
     const secondUserStart = Number((await getAccount(connection, secondUserUsdcAccount)).amount);
-    const referralStart = Number((await getAccount(connection, referralUsdcAccount)).amount);
+    const referralStart = await referralAccrued();
     const deployerStart = Number((await getAccount(connection, deployerUsdcAccount.address)).amount);
     const poolStart = Number((await getAccount(connection, programUsdcAccount.address)).amount);
 
     console.log('\n💰 Initial Balances:');
     console.log('👤 Second user USDC:', secondUserStart / 1e6);
-    console.log('🤝 Referral USDC:', referralStart / 1e6);
+    console.log('🤝 Referral accrued:', referralStart / 1e6);
     console.log('💼 Deployer USDC:', deployerStart / 1e6);
     console.log('🏦 Pool USDC:', poolStart / 1e6);
 
@@ -1042,9 +1095,10 @@ describe('UP ONLY TESTS', () => {
 
     // Buy tokens
     const tx = await program.methods
-      .buyAndLockToken(new anchor.BN(1_000_000_000), new anchor.BN(0), null)
+      .buyAndLockToken(new anchor.BN(1_000_000_000), new anchor.BN(3_600), null, false, null)
       .accounts({
         user: secondUser.publicKey,
+        beneficiary: secondUser.publicKey,
         userState: secondUserStatePda,
         userUsdcAccount: secondUserUsdcAccount,
         deployerUsdcAccount: deployerUsdcAccount.address,
@@ -1054,7 +1108,11 @@ describe('UP ONLY TESTS', () => {
         vaultAuthority: secondUser.publicKey,
         mintAuthority: mintAuthorityPda,
         metadata: metadataPda,
-        referralUsdcAccount: referralUsdcAccount,
+        referralAccount: referralAccountPda,
+        referralPool: referralPoolPda,
+        teamTreasury: teamTreasuryPda,
+        feeSplit: feeSplitPda,
+        protocolState: protocolStatePda,
         founderPoolTokenAccount: founderPoolTokenAccount.address,
         foundersPool: foundersPoolPda,
         tokenProgram: TOKEN_PROGRAM_ID,
@@ -1064,7 +1122,7 @@ describe('UP ONLY TESTS', () => {
 
     // Get final balances
     const secondUserEnd = Number((await getAccount(connection, secondUserUsdcAccount)).amount);
-    const referralEnd = Number((await getAccount(connection, referralUsdcAccount)).amount);
+    const referralEnd = await referralAccrued();
     const deployerEnd = Number((await getAccount(connection, deployerUsdcAccount.address)).amount);
     const poolEnd = Number((await getAccount(connection, programUsdcAccount.address)).amount);
 
@@ -1081,7 +1139,7 @@ describe('UP ONLY TESTS', () => {
 
     console.log('\n💰 Final Balances:');
     console.log('👤 Second user USDC:', secondUserEnd / 1e6);
-    console.log('🤝 Referral USDC:', referralEnd / 1e6);
+    console.log('🤝 Referral accrued:', referralEnd / 1e6);
     console.log('💼 Deployer USDC:', deployerEnd / 1e6);
     console.log('🏦 Pool USDC:', poolEnd / 1e6);
 
@@ -1229,7 +1287,8 @@ describe('UP ONLY TESTS', () => {
         mintAuthority: mintAuthorityPda,
         founderPoolTokenAccount: founderPoolTokenAccount.address,
         foundersPool: foundersPoolPda,
-        referralUsdcAccount: null,
+        referralAccount: null,
+        referralPool: null,
         tokenProgram: TOKEN_PROGRAM_ID,
       })
       .signers([buyer])
@@ -1359,9 +1418,10 @@ describe('UP ONLY TESTS', () => {
     );
 
     const tx = await program.methods
-      .buyAndLockToken(new anchor.BN(1_000_000_000), new anchor.BN(0), null)
+      .buyAndLockToken(new anchor.BN(1_000_000_000), new anchor.BN(3_600), null, false, null)
       .accounts({
         user: lockedUser.publicKey,
+        beneficiary: lockedUser.publicKey,
         lockState: lockStatePda,
         userUsdcAccount: lockedUserUsdcAccount,
         deployerUsdcAccount: usdcTokenAccount,
@@ -1371,7 +1431,11 @@ describe('UP ONLY TESTS', () => {
         vaultAuthority: vaultAuthorityPda,
         mintAuthority: mintAuthorityPda,
         metadata: metadataPda,
-        referralUsdcAccount: null,
+        referralAccount: null,
+        referralPool: null,
+        teamTreasury: teamTreasuryPda,
+        feeSplit: feeSplitPda,
+        protocolState: protocolStatePda,
         founderPoolTokenAccount: founderPoolTokenAccount.address,
         foundersPool: foundersPoolPda,
         tokenProgram: TOKEN_PROGRAM_ID,
@@ -1508,9 +1572,10 @@ describe('UP ONLY TESTS', () => {
     let failed = false;
     try {
       await program.methods
-        .buyAndLockToken(new anchor.BN(1_000_000_000), new anchor.BN(7), null) // 1000 USDC, lock 7 days again
+        .buyAndLockToken(new anchor.BN(1_000_000_000), new anchor.BN(7 * 24 * 3_600), null, false, null) // 1000 USDC, lock 7 days (in seconds) again
         .accounts({
           user: lockedUser.publicKey,
+          beneficiary: lockedUser.publicKey,
           lockState: lockStatePda,
           userUsdcAccount: lockedUserUsdcAccount,
           deployerUsdcAccount: usdcTokenAccount,
@@ -1520,7 +1585,11 @@ describe('UP ONLY TESTS', () => {
           vaultAuthority: vaultAuthorityPda,
           mintAuthority: mintAuthorityPda,
           metadata: metadataPda,
-          referralUsdcAccount: null,
+          referralAccount: null,
+          referralPool: null,
+          teamTreasury: teamTreasuryPda,
+          feeSplit: feeSplitPda,
+          protocolState: protocolStatePda,
           founderPoolTokenAccount: founderPoolTokenAccount.address,
           foundersPool: foundersPoolPda,
           tokenProgram: TOKEN_PROGRAM_ID,